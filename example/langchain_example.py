@@ -0,0 +1,32 @@
+import asyncio
+import os
+
+from dotenv import load_dotenv
+from langchain_core.messages import AIMessage, HumanMessage
+from langchain_core.tools import tool
+
+from tlsn_langchain import ChatTlsn
+
+load_dotenv()
+
+
+@tool
+def tavily_search_results_json(query: str) -> str:
+    """A search engine optimized for comprehensive, accurate, and trusted results. Useful for when you need to answer questions about current events. Input should be a search query."""
+    return "It is sunny in San Francisco."
+
+
+async def main():
+    model = ChatTlsn(model="gpt-4o", api_key=os.getenv("REDPILL_API_KEY"), top_p=0.85, temperature=0.3)
+    model = model.bind_tools([tavily_search_results_json])
+
+    message = await model.ainvoke([
+        HumanMessage("hi im bob! and i live in sf"),
+        AIMessage("Hi Bob! It's great to meet you. How can I assist you today?"),
+        HumanMessage("whats the weather where I live?"),
+    ])
+    print("Response: ", message)
+    print("Proof:", message.response_metadata["proof"].replace("\n", "").replace(" ", ""))
+
+print("Running the async function")
+asyncio.run(main())
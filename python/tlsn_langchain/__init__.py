@@ -0,0 +1,2 @@
+from .tlsn_langchain import *  # noqa: F401,F403
+from .chat_model import ChatTlsn  # noqa: F401
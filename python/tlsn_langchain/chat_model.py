@@ -0,0 +1,204 @@
+"""LangChain chat model backed by notarised model API requests."""
+
+import json
+from typing import Any, Callable, Dict, List, Optional, Sequence, Tuple, Type, Union
+
+from langchain_core.callbacks import AsyncCallbackManagerForLLMRun, CallbackManagerForLLMRun
+from langchain_core.language_models import BaseChatModel, LanguageModelInput
+from langchain_core.messages import AIMessage, BaseMessage
+from langchain_core.messages.tool import tool_call as create_tool_call
+from langchain_core.outputs import ChatGeneration, ChatResult
+from langchain_core.runnables import Runnable
+from langchain_core.tools import BaseTool
+from langchain_core.utils.function_calling import convert_to_openai_tool
+from pydantic import SecretStr
+
+from . import tlsn_langchain as _native
+
+
+class ChatTlsn(BaseChatModel):
+    """Chat model whose every response comes with a TLSNotary proof.
+
+    The proof JSON is attached to the `response_metadata["proof"]` of the returned `AIMessage`.
+    """
+
+    model: str
+    api_key: SecretStr
+    """Kept out of the `repr` and of the serialized model, a plain string is accepted."""
+    temperature: Optional[float] = None
+    top_p: Optional[float] = None
+    max_tokens: Optional[int] = None
+    stop: Optional[List[str]] = None
+    seed: Optional[int] = None
+    n: Optional[int] = None
+    presence_penalty: Optional[float] = None
+    frequency_penalty: Optional[float] = None
+    logprobs: Optional[bool] = None
+    user: Optional[str] = None
+    response_format: Optional[Union[str, Dict[str, Any]]] = None
+    """`"json_object"`, or a `response_format` dict such as `{"type": "json_schema", ...}`."""
+    system_prompt: Optional[str] = None
+    """Sent as a `system` message ahead of every conversation."""
+    api_settings: Optional[Any] = None
+    """`tlsn_langchain.ModelApiSettings` selecting the model API endpoint."""
+    disclosure: Optional[Any] = None
+    """`tlsn_langchain.DisclosurePolicy` selecting what the proof reveals of the bodies."""
+    notary_settings: Optional[Any] = None
+    """`tlsn_langchain.NotarySettings` selecting the notary, an in-process dummy notary by default."""
+    redaction: Optional[Any] = None
+    """`tlsn_langchain.RedactionPolicy` selecting the redacted headers and secrets."""
+    prove_errors: bool = False
+    validate_model: bool = False
+    """Checks `model` against the notarised model list of the API before the inference request."""
+
+    @property
+    def lc_secrets(self) -> Dict[str, str]:
+        return {"api_key": "REDPILL_API_KEY"}
+
+    @property
+    def _llm_type(self) -> str:
+        return "tlsn-langchain"
+
+    @property
+    def _identifying_params(self) -> Dict[str, Any]:
+        return {"model": self.model, **json.loads(self._params().to_json())}
+
+    def bind_tools(
+        self,
+        tools: Sequence[Union[Dict[str, Any], Type, Callable, BaseTool]],
+        **kwargs: Any,
+    ) -> Runnable[LanguageModelInput, BaseMessage]:
+        formatted_tools = [convert_to_openai_tool(tool) for tool in tools]
+        return super().bind(tools=formatted_tools, **kwargs)
+
+    def _generate(
+        self,
+        messages: List[BaseMessage],
+        stop: Optional[List[str]] = None,
+        run_manager: Optional[CallbackManagerForLLMRun] = None,
+        **kwargs: Any,
+    ) -> ChatResult:
+        args, options = self._exec_arguments(messages, stop, **kwargs)
+        return _chat_result(_native.exec_sync(*args, **options))
+
+    async def _agenerate(
+        self,
+        messages: List[BaseMessage],
+        stop: Optional[List[str]] = None,
+        run_manager: Optional[AsyncCallbackManagerForLLMRun] = None,
+        **kwargs: Any,
+    ) -> ChatResult:
+        args, options = self._exec_arguments(messages, stop, **kwargs)
+        return _chat_result(await _native.exec(*args, **options))
+
+    def _exec_arguments(
+        self, messages: List[BaseMessage], stop: Optional[List[str]], **kwargs: Any
+    ) -> Tuple[Tuple[Any, ...], Dict[str, Any]]:
+        """Arguments shared by `exec` and `exec_sync`."""
+        args = (
+            self.model,
+            self.api_key.get_secret_value(),
+            # LangChain messages and OpenAI tool dicts are converted by the native module
+            list(messages),
+            list(kwargs.get("tools", [])),
+        )
+        options = {
+            "params": self._params(stop, **kwargs),
+            "system_prompt": self.system_prompt,
+            "api_settings": self.api_settings,
+            "disclosure": self.disclosure,
+            "prove_errors": self.prove_errors,
+            "notary_settings": self.notary_settings,
+            "redaction": self.redaction,
+            "validate_model": self.validate_model,
+        }
+        return args, options
+
+    def _params(self, stop: Optional[List[str]] = None, **kwargs: Any) -> Any:
+        """Request parameters of the model, overridden by the call's `stop` and bound `tool_choice`."""
+        return _native.ChatRequestParams(
+            temperature=self.temperature,
+            top_p=self.top_p,
+            max_tokens=self.max_tokens,
+            stop=stop if stop is not None else self.stop,
+            seed=self.seed,
+            tool_choice=_json_option(kwargs.get("tool_choice")),
+            response_format=_json_option(kwargs.get("response_format", self.response_format)),
+            n=self.n,
+            presence_penalty=self.presence_penalty,
+            frequency_penalty=self.frequency_penalty,
+            logprobs=self.logprobs,
+            user=self.user,
+        )
+
+
+def _chat_result(response: Any) -> ChatResult:
+    """One generation per choice of the `NotarisedResponse`, each carrying the proof."""
+    generations = [
+        ChatGeneration(
+            message=_convert_response(choice["message"], choice.get("finish_reason"), response),
+            generation_info={"finish_reason": choice.get("finish_reason"), "logprobs": choice.get("logprobs")},
+        )
+        for choice in response.choices
+    ]
+    llm_output = {
+        "token_usage": response.usage,
+        "model_name": response.model,
+        "system_fingerprint": response.system_fingerprint,
+    }
+    return ChatResult(generations=generations, llm_output=llm_output)
+
+
+def _json_option(value: Any) -> Optional[str]:
+    """Passes strings through and encodes dicts, as `ChatRequestParams` expects."""
+    if value is None or isinstance(value, str):
+        return value
+    return json.dumps(value)
+
+
+def _convert_response(message: Dict[str, Any], finish_reason: Optional[str], response: Any) -> AIMessage:
+    """Converts an assistant message of the response returned by `exec` into an `AIMessage`."""
+    tool_calls = []
+    invalid_tool_calls = []
+    for tool_call in message.get("tool_calls") or []:
+        function = tool_call.get("function", {})
+        try:
+            args = json.loads(function.get("arguments") or "{}")
+            tool_calls.append(create_tool_call(name=function.get("name", ""), args=args, id=tool_call.get("id")))
+        except json.JSONDecodeError as e:
+            invalid_tool_calls.append(
+                {
+                    "name": function.get("name"),
+                    "args": function.get("arguments"),
+                    "id": tool_call.get("id"),
+                    "error": str(e),
+                    "type": "invalid_tool_call",
+                }
+            )
+
+    return AIMessage(
+        content=message.get("content") or "",
+        tool_calls=tool_calls,
+        invalid_tool_calls=invalid_tool_calls,
+        usage_metadata=_convert_usage(response.usage),
+        id=response.id,
+        response_metadata={
+            "proof": response.proof.to_json(),
+            "notary_public_key": response.proof.notary_public_key,
+            "token_usage": response.usage,
+            "model_name": response.model,
+            "system_fingerprint": response.system_fingerprint,
+            "created": response.created,
+            "finish_reason": finish_reason,
+        },
+    )
+
+
+def _convert_usage(usage: Optional[Dict[str, Any]]) -> Optional[Dict[str, int]]:
+    if not usage or "prompt_tokens" not in usage or "completion_tokens" not in usage:
+        return None
+    return {
+        "input_tokens": usage["prompt_tokens"],
+        "output_tokens": usage["completion_tokens"],
+        "total_tokens": usage.get("total_tokens", usage["prompt_tokens"] + usage["completion_tokens"]),
+    }
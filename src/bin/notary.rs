@@ -0,0 +1,214 @@
+//! Standalone notary service, so several provers (e.g. Python processes on a LAN) can share one notary.
+//!
+//! Provers connect with `NotarySettings(host, port, protocol="tcp")` or `protocol="websocket"`.
+
+use anyhow::{Context, Result};
+use clap::{Parser, ValueEnum};
+use p256::ecdsa::SigningKey;
+use p256::pkcs8::{EncodePublicKey, LineEnding};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tlsn_langchain::{
+    load_notary_key, serve_session, NotaryKey, NotarySettings, ServiceLimits, DEFAULT_HANDSHAKE_TIMEOUT,
+    DEFAULT_MAX_RECV_DATA, DEFAULT_MAX_SENT_DATA, DEFAULT_SESSION_TIMEOUT,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_util::compat::TokioAsyncReadCompatExt;
+use tracing::{error, info, warn, Instrument};
+use tracing_subscriber::EnvFilter;
+use ws_stream_tungstenite::WsStream;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Transport {
+    Tcp,
+    Websocket,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Notary service for tlsn-langchain provers")]
+struct Args {
+    /// Address to listen on
+    #[arg(long, default_value = "0.0.0.0:7047")]
+    listen: SocketAddr,
+
+    #[arg(long, value_enum, default_value = "tcp")]
+    transport: Transport,
+
+    /// PKCS#8 PEM file with the signing key
+    #[arg(long, conflicts_with = "key_env")]
+    key_path: Option<PathBuf>,
+
+    /// Environment variable holding the PKCS#8 PEM signing key
+    #[arg(long)]
+    key_env: Option<String>,
+
+    /// Writes the public key of the notary to this file, for the verifiers
+    #[arg(long)]
+    public_key_path: Option<PathBuf>,
+
+    /// Sessions notarised at the same time, further connections are refused
+    #[arg(long, default_value_t = 8)]
+    max_concurrent_sessions: usize,
+
+    /// Largest request transcript a session may notarise, in bytes
+    #[arg(long, default_value_t = DEFAULT_MAX_SENT_DATA)]
+    max_sent_data: usize,
+
+    /// Largest response transcript a session may notarise, in bytes
+    #[arg(long, default_value_t = DEFAULT_MAX_RECV_DATA)]
+    max_recv_data: usize,
+
+    /// Longest a session may take, in seconds, after which its connection is closed
+    #[arg(long, default_value_t = DEFAULT_SESSION_TIMEOUT.as_secs())]
+    session_timeout: u64,
+
+    #[arg(long, value_enum, default_value = "text")]
+    log_format: LogFormat,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    match args.log_format {
+        LogFormat::Text => tracing_subscriber::fmt().with_env_filter(filter).init(),
+        LogFormat::Json => tracing_subscriber::fmt().json().with_env_filter(filter).init(),
+    }
+
+    let notary_key = match (&args.key_path, &args.key_env) {
+        (Some(path), _) => NotaryKey::Path(path.clone()),
+        (None, Some(var)) => NotaryKey::Env(var.clone()),
+        (None, None) => {
+            warn!("No signing key configured, signing with an ephemeral key");
+            NotaryKey::Ephemeral
+        }
+    };
+    let signing_key = load_notary_key(&NotarySettings {
+        notary_key,
+        ..NotarySettings::default()
+    })?;
+
+    let public_key = p256::PublicKey::from(signing_key.verifying_key())
+        .to_public_key_pem(LineEnding::LF)
+        .context("Error encoding the public key")?;
+    info!(public_key = %public_key, "Notary public key");
+    if let Some(path) = &args.public_key_path {
+        std::fs::write(path, &public_key)
+            .with_context(|| format!("Error writing the public key to {}", path.display()))?;
+    }
+
+    let limits = ServiceLimits {
+        max_sent_data: args.max_sent_data,
+        max_recv_data: args.max_recv_data,
+        handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+        session_timeout: Duration::from_secs(args.session_timeout),
+    };
+    let signing_key = Arc::new(signing_key);
+    let sessions = Arc::new(Semaphore::new(args.max_concurrent_sessions));
+
+    let listener = TcpListener::bind(args.listen)
+        .await
+        .with_context(|| format!("Error listening on {}", args.listen))?;
+    info!(address = %args.listen, transport = ?args.transport, "Notary listening");
+
+    loop {
+        let (socket, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                error!(error = %e, "Error accepting a connection");
+                continue;
+            }
+        };
+
+        let Ok(permit) = sessions.clone().try_acquire_owned() else {
+            warn!(%peer, "Refusing connection, too many concurrent sessions");
+            continue;
+        };
+
+        let signing_key = signing_key.clone();
+        let limits = limits.clone();
+        let transport = args.transport;
+        tokio::spawn(
+            run_session(socket, transport, signing_key, limits, permit)
+                .instrument(tracing::info_span!("session", %peer)),
+        );
+    }
+}
+
+/// Serves one connection within the session timeout, then releases its session slot
+async fn run_session(
+    socket: TcpStream,
+    transport: Transport,
+    signing_key: Arc<SigningKey>,
+    limits: ServiceLimits,
+    permit: OwnedSemaphorePermit,
+) {
+    let started = Instant::now();
+    let session = handle_connection(socket, transport, &signing_key, &limits);
+    match tokio::time::timeout(limits.session_timeout, session).await {
+        Ok(Ok(())) => info!(elapsed_ms = started.elapsed().as_millis() as u64, "Session notarised"),
+        Ok(Err(e)) => warn!(error = %format!("{e:#}"), "Session failed"),
+        Err(_) => warn!(timeout_secs = limits.session_timeout.as_secs(), "Session timed out"),
+    }
+    drop(permit);
+}
+
+async fn handle_connection(
+    socket: TcpStream,
+    transport: Transport,
+    signing_key: &SigningKey,
+    limits: &ServiceLimits,
+) -> Result<()> {
+    match transport {
+        Transport::Tcp => serve_session(socket.compat(), signing_key, limits).await?,
+        Transport::Websocket => {
+            let accept = async_tungstenite::tokio::accept_async(socket);
+            let websocket = tokio::time::timeout(limits.handshake_timeout, accept)
+                .await
+                .context("The client did not open the WebSocket in time")?
+                .context("Error accepting the WebSocket")?;
+            serve_session(WsStream::new(websocket), signing_key, limits).await?
+        }
+    };
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_idle_client_releases_its_session_slot() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        // Connected but never sends its session request
+        let _idle_client = TcpStream::connect(listener.local_addr()?).await?;
+        let (socket, _) = listener.accept().await?;
+
+        let sessions = Arc::new(Semaphore::new(1));
+        let permit = sessions.clone().try_acquire_owned()?;
+        let limits = ServiceLimits {
+            handshake_timeout: Duration::from_millis(50),
+            ..ServiceLimits::default()
+        };
+        let signing_key = Arc::new(SigningKey::random(&mut rand::rngs::OsRng));
+
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            run_session(socket, Transport::Tcp, signing_key, limits, permit),
+        )
+        .await
+        .context("The idle session was not closed")?;
+        assert_eq!(sessions.available_permits(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+//! Verifies a proof produced by `notarised_model_request` and prints what it reveals.
+//!
+//! Exit codes, for audit scripts:
+//! - 0: the proof is valid
+//! - 1: usage or I/O error (e.g. unreadable file, invalid public key)
+//! - 2: malformed proof
+//! - 3: invalid notary signature or server certificate
+//! - 4: the proof is for another server
+
+use anyhow::{Context, Result};
+use chrono::DateTime;
+use clap::Parser;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use tlsn_langchain::{verify_proof_with_root_ca, VerificationFailure, VerifiedProof, DEFAULT_SERVER_DOMAIN, REDACTED_BYTE};
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Verifies a tlsn-langchain proof")]
+struct Args {
+    /// Proof JSON file
+    proof: PathBuf,
+
+    /// PEM file with the public key of the notary
+    #[arg(long)]
+    notary_key: PathBuf,
+
+    /// Server the session must have been held with
+    #[arg(long, default_value = DEFAULT_SERVER_DOMAIN)]
+    server_name: String,
+
+    /// PEM file with extra root certificates trusted for the server certificate (e.g. of a private PKI)
+    #[arg(long)]
+    root_ca: Option<PathBuf>,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    match run(&args) {
+        Ok(verified) => {
+            print_verified(&verified);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Error: {e:#}");
+            let code = match e.downcast_ref::<VerificationFailure>() {
+                Some(VerificationFailure::MalformedProof) => 2,
+                Some(VerificationFailure::InvalidSignature) => 3,
+                Some(VerificationFailure::WrongServer) => 4,
+                None => 1,
+            };
+            ExitCode::from(code)
+        }
+    }
+}
+
+fn run(args: &Args) -> Result<VerifiedProof> {
+    let proof_json = std::fs::read_to_string(&args.proof)
+        .with_context(|| format!("Error reading {}", args.proof.display()))?;
+    let notary_pubkey_pem = std::fs::read_to_string(&args.notary_key)
+        .with_context(|| format!("Error reading {}", args.notary_key.display()))?;
+
+    let root_ca_pem = args
+        .root_ca
+        .as_ref()
+        .map(|path| {
+            std::fs::read_to_string(path).with_context(|| format!("Error reading {}", path.display()))
+        })
+        .transpose()?;
+
+    verify_proof_with_root_ca(&proof_json, &notary_pubkey_pem, &args.server_name, root_ca_pem.as_deref())
+}
+
+fn print_verified(verified: &VerifiedProof) {
+    let time = DateTime::from_timestamp(verified.time as i64, 0)
+        .map(|time| time.to_rfc3339())
+        .unwrap_or_else(|| verified.time.to_string());
+
+    println!("Server name: {}", verified.server_name);
+    println!("Notarised at: {time}");
+    println!();
+    println!("Redacted bytes are shown as `{}`", REDACTED_BYTE as char);
+    println!("--- Request ---");
+    println!("{}", verified.sent);
+    println!("--- Response ---");
+    println!("{}", verified.recv);
+    println!("---");
+
+    // Only fields revealed along with everything before them are printed, a field read from after a
+    // redaction could be a lookalike planted elsewhere in the request
+    println!(
+        "Model: {}",
+        verified
+            .model_id()
+            .unwrap_or_else(|| "(not revealed before the first redaction)".to_string())
+    );
+    println!(
+        "System prompt: {}",
+        verified
+            .system_prompt()
+            .unwrap_or_else(|| "(not revealed before the first redaction)".to_string())
+    );
+    let messages = verified.assistant_messages();
+    if messages.is_empty() {
+        println!("Assistant message: (not revealed)");
+    }
+    for message in messages {
+        let message = serde_json::to_string_pretty(&message).unwrap_or_else(|_| message.to_string());
+        println!("Assistant message: {message}");
+    }
+}
@@ -0,0 +1,748 @@
+use crate::config::ApiProvider;
+use anyhow::{bail, Context, Result};
+use std::ops::Range;
+use tracing::debug;
+
+/// Which parts of the JSON bodies of one direction of the transcript are revealed in the proof
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum BodyDisclosure {
+    /// The bodies are revealed as a whole (only the header based redaction applies)
+    #[default]
+    RevealAll,
+    /// The values at these paths are redacted, everything else is revealed
+    Hide(Vec<JsonPath>),
+    /// Only the members at these paths are revealed, the rest of the bodies is redacted
+    RevealOnly(Vec<JsonPath>),
+    /// The bodies are revealed from their start through the value at this path, the rest is redacted.
+    /// Without redactions before it, a verifier can tell where the value sits in the document.
+    RevealPrefix(JsonPath),
+}
+
+impl BodyDisclosure {
+    /// Reveals the requests up to the system prompt, where `provider` sends the setup prompt: the first
+    /// message of the OpenAI requests, or the `system` field of the Anthropic ones (whose first message is
+    /// a user turn). Only the model (and the Anthropic `max_tokens`) precede it.
+    pub fn system_prompt_only(provider: ApiProvider) -> Self {
+        let path = match provider {
+            ApiProvider::OpenAi => "messages[0]",
+            ApiProvider::Anthropic => "system",
+        };
+        Self::RevealPrefix(JsonPath::parse(path).expect("The system prompt paths are valid"))
+    }
+
+    /// Makes sure the values at `paths` are revealed, whatever the other rules are (a revealed prefix
+    /// is left as is)
+    pub fn revealing(self, paths: &[JsonPath]) -> Self {
+        match self {
+            Self::RevealAll => Self::RevealAll,
+            Self::RevealPrefix(path) => Self::RevealPrefix(path),
+            Self::Hide(hidden) => Self::Hide(
+                hidden
+                    .into_iter()
+                    .filter(|hidden| !paths.iter().any(|path| hidden.starts_with(path)))
+                    .collect(),
+            ),
+            Self::RevealOnly(mut revealed) => {
+                for path in paths {
+                    if !revealed.contains(path) {
+                        revealed.push(path.clone());
+                    }
+                }
+                Self::RevealOnly(revealed)
+            }
+        }
+    }
+}
+
+/// One step of a [`JsonPath`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathSegment {
+    Key(String),
+    AnyKey,
+    Index(usize),
+    AnyIndex,
+}
+
+/// Path to values of a JSON document, such as `messages[*].content` or `choices[0].message.content`.
+///
+/// Object keys are separated by `.`, array indices are written in brackets and `*` matches any
+/// key or index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonPath {
+    segments: Vec<PathSegment>,
+}
+
+impl JsonPath {
+    pub fn parse(path: &str) -> Result<Self> {
+        let mut segments = vec![];
+
+        for part in path.split('.') {
+            let (key, mut indices) = match part.find('[') {
+                Some(idx) => (&part[..idx], &part[idx..]),
+                None => (part, ""),
+            };
+
+            match key {
+                "" if segments.is_empty() && !indices.is_empty() => {}
+                "" => bail!("Empty key in JSON path `{path}`"),
+                "*" => segments.push(PathSegment::AnyKey),
+                key => segments.push(PathSegment::Key(key.to_string())),
+            }
+
+            while !indices.is_empty() {
+                let end = indices
+                    .find(']')
+                    .with_context(|| format!("Unclosed `[` in JSON path `{path}`"))?;
+                let index = &indices[1..end];
+                segments.push(match index {
+                    "*" => PathSegment::AnyIndex,
+                    index => PathSegment::Index(
+                        index
+                            .parse()
+                            .with_context(|| format!("Invalid index `{index}` in JSON path `{path}`"))?,
+                    ),
+                });
+
+                indices = &indices[end + 1..];
+                if !indices.is_empty() && !indices.starts_with('[') {
+                    bail!("Unexpected `{indices}` in JSON path `{path}`");
+                }
+            }
+        }
+
+        Ok(Self { segments })
+    }
+
+    /// Whether this path points to `prefix` or to a value nested in it
+    fn starts_with(&self, prefix: &JsonPath) -> bool {
+        self.segments.starts_with(&prefix.segments)
+    }
+
+    pub(crate) fn matches(&self, path: &[PathSegment]) -> bool {
+        self.segments.len() == path.len()
+            && self.segments.iter().zip(path).all(|(pattern, segment)| {
+                match (pattern, segment) {
+                    (PathSegment::AnyKey, PathSegment::Key(_)) => true,
+                    (PathSegment::AnyIndex, PathSegment::Index(_)) => true,
+                    (pattern, segment) => pattern == segment,
+                }
+            })
+    }
+}
+
+/// A value of a JSON document and where it sits in the source bytes
+#[derive(Debug)]
+struct JsonSpan {
+    path: Vec<PathSegment>,
+    /// The value itself, including the quotes of strings and the brackets of containers
+    value: Range<usize>,
+    /// The value together with its key when it is an object member
+    member: Range<usize>,
+}
+
+/// Byte ranges of the bodies of `transcript` (the sent or received data of a session) that the
+/// disclosure rules keep private.
+pub(crate) fn private_body_ranges(
+    transcript: &[u8],
+    disclosure: &BodyDisclosure,
+    is_response: bool,
+) -> Vec<Range<usize>> {
+    if *disclosure == BodyDisclosure::RevealAll {
+        return vec![];
+    }
+
+    let mut private_ranges = vec![];
+    for body in http_bodies(transcript, is_response) {
+        let content = body.content(transcript);
+
+        let ranges = private_content_ranges(&content, disclosure, body.event_stream)
+            .unwrap_or_else(|e| {
+                // A body that cannot be analysed could hold anything, keep it private
+                debug!("Redacting a whole body that could not be parsed: {e:#}");
+                vec![0..content.len()]
+            });
+
+        private_ranges.extend(ranges.iter().flat_map(|range| body.map_range(range)));
+    }
+
+    private_ranges
+}
+
+/// Decoded bodies of the HTTP messages of a transcript, and whether each is an event stream
+pub(crate) fn http_body_contents(transcript: &[u8], is_response: bool) -> Vec<(Vec<u8>, bool)> {
+    http_bodies(transcript, is_response)
+        .into_iter()
+        .map(|body| (body.content(transcript), body.event_stream))
+        .collect()
+}
+
+/// Decoded bodies of the HTTP messages of a transcript, each with the length of its prefix that holds
+/// none of the `redacted` ranges of the transcript
+pub(crate) fn http_body_revealed_prefixes(
+    transcript: &[u8],
+    redacted: &[Range<usize>],
+    is_response: bool,
+) -> Vec<(Vec<u8>, usize)> {
+    http_bodies(transcript, is_response)
+        .into_iter()
+        .map(|body| {
+            let revealed_len = body
+                .segments
+                .iter()
+                .flat_map(|segment| segment.clone())
+                .take_while(|pos| !redacted.iter().any(|range| range.contains(pos)))
+                .count();
+            (body.content(transcript), revealed_len)
+        })
+        .collect()
+}
+
+/// Paths and ranges of the values of a JSON document that end within its first `revealed_len` bytes.
+///
+/// Only that prefix is scanned, so the path of each value is its actual place in the document, whatever
+/// the redacted rest holds. A number or literal cut by the end of the prefix is left out.
+pub(crate) fn revealed_prefix_values(document: &[u8], revealed_len: usize) -> Vec<(Vec<PathSegment>, Range<usize>)> {
+    let prefix = &document[..revealed_len.min(document.len())];
+    let mut scanner = JsonScanner {
+        src: prefix,
+        pos: 0,
+        spans: vec![],
+    };
+
+    scanner.skip_whitespace();
+    let start = scanner.pos;
+    // Stops at the end of the prefix, keeping the values completed before it
+    let _ = scanner.value(&mut vec![], start);
+
+    scanner
+        .spans
+        .into_iter()
+        .filter(|span| span.value.end < prefix.len() || matches!(prefix[span.value.end - 1], b'"' | b'}' | b']'))
+        .map(|span| (span.path, span.value))
+        .collect()
+}
+
+fn private_content_ranges(
+    content: &[u8],
+    disclosure: &BodyDisclosure,
+    event_stream: bool,
+) -> Result<Vec<Range<usize>>> {
+    let documents = if event_stream {
+        event_stream_documents(content)
+    } else {
+        vec![0..content.len()]
+    };
+
+    let mut private_ranges = vec![];
+    let mut revealed_ranges = vec![];
+    for document in documents {
+        let spans = json_spans(&content[document.clone()])?;
+        let offset = |range: &Range<usize>| (range.start + document.start)..(range.end + document.start);
+
+        match disclosure {
+            BodyDisclosure::RevealAll => {}
+            BodyDisclosure::Hide(paths) => private_ranges.extend(
+                spans
+                    .iter()
+                    .filter(|span| paths.iter().any(|path| path.matches(&span.path)))
+                    .map(|span| offset(&span.value)),
+            ),
+            BodyDisclosure::RevealOnly(paths) => revealed_ranges.extend(
+                spans
+                    .iter()
+                    .filter(|span| paths.iter().any(|path| path.matches(&span.path)))
+                    .map(|span| offset(&span.member)),
+            ),
+            BodyDisclosure::RevealPrefix(path) => {
+                let end = spans
+                    .iter()
+                    .filter(|span| path.matches(&span.path))
+                    .min_by_key(|span| span.value.start)
+                    .map_or(0, |span| span.value.end);
+                private_ranges.push(offset(&(end..document.len())));
+            }
+        }
+    }
+
+    if let BodyDisclosure::RevealOnly(_) = disclosure {
+        revealed_ranges.sort_by_key(|range| range.start);
+
+        let mut last_end = 0;
+        for range in revealed_ranges {
+            if range.start > last_end {
+                private_ranges.push(last_end..range.start);
+            }
+            last_end = last_end.max(range.end);
+        }
+        if last_end < content.len() {
+            private_ranges.push(last_end..content.len());
+        }
+    }
+
+    Ok(private_ranges)
+}
+
+/// Ranges of the JSON payloads of the `data:` lines of an event stream
+fn event_stream_documents(content: &[u8]) -> Vec<Range<usize>> {
+    let mut documents = vec![];
+    let mut line_start = 0;
+
+    for line in content.split(|&b| b == b'\n') {
+        let line_end = line_start + line.len();
+        if let Some(data) = line.strip_prefix(b"data:") {
+            let leading_whitespace = data.len() - data.trim_ascii_start().len();
+            let data = data.trim_ascii();
+            if data.starts_with(b"{") || data.starts_with(b"[") {
+                let start = line_start + b"data:".len() + leading_whitespace;
+                documents.push(start..start + data.len());
+            }
+        }
+        line_start = line_end + 1;
+    }
+
+    documents
+}
+
+fn json_spans(document: &[u8]) -> Result<Vec<JsonSpan>> {
+    let mut scanner = JsonScanner {
+        src: document,
+        pos: 0,
+        spans: vec![],
+    };
+
+    scanner.skip_whitespace();
+    let start = scanner.pos;
+    scanner.value(&mut vec![], start)?;
+    scanner.skip_whitespace();
+    if scanner.pos != document.len() {
+        bail!("Trailing data after the JSON document at byte {}", scanner.pos);
+    }
+
+    Ok(scanner.spans)
+}
+
+/// Deepest nesting the scanner follows, deeper documents are rejected (and their body redacted as a whole)
+/// instead of exhausting the stack
+const MAX_JSON_DEPTH: usize = 128;
+
+/// Minimal JSON scanner that records the position of every value instead of decoding it
+struct JsonScanner<'a> {
+    src: &'a [u8],
+    pos: usize,
+    spans: Vec<JsonSpan>,
+}
+
+impl JsonScanner<'_> {
+    fn value(&mut self, path: &mut Vec<PathSegment>, member_start: usize) -> Result<()> {
+        let start = self.pos;
+        if path.len() > MAX_JSON_DEPTH {
+            bail!("JSON document nested deeper than {MAX_JSON_DEPTH} levels at byte {start}");
+        }
+
+        match self.peek()? {
+            b'{' => {
+                self.pos += 1;
+                self.skip_whitespace();
+                if self.peek()? == b'}' {
+                    self.pos += 1;
+                } else {
+                    loop {
+                        self.skip_whitespace();
+                        let key_start = self.pos;
+                        self.string()?;
+                        let raw_key = &self.src[key_start..self.pos];
+                        let key = serde_json::from_slice::<String>(raw_key)
+                            .with_context(|| format!("Invalid object key {}", String::from_utf8_lossy(raw_key)))?;
+
+                        self.skip_whitespace();
+                        self.expect(b':')?;
+                        self.skip_whitespace();
+
+                        path.push(PathSegment::Key(key));
+                        self.value(path, key_start)?;
+                        path.pop();
+
+                        self.skip_whitespace();
+                        match self.next()? {
+                            b',' => continue,
+                            b'}' => break,
+                            b => bail!("Unexpected `{}` in object at byte {}", b as char, self.pos - 1),
+                        }
+                    }
+                }
+            }
+            b'[' => {
+                self.pos += 1;
+                self.skip_whitespace();
+                if self.peek()? == b']' {
+                    self.pos += 1;
+                } else {
+                    for index in 0.. {
+                        self.skip_whitespace();
+                        path.push(PathSegment::Index(index));
+                        self.value(path, self.pos)?;
+                        path.pop();
+
+                        self.skip_whitespace();
+                        match self.next()? {
+                            b',' => continue,
+                            b']' => break,
+                            b => bail!("Unexpected `{}` in array at byte {}", b as char, self.pos - 1),
+                        }
+                    }
+                }
+            }
+            b'"' => {
+                self.string()?;
+            }
+            b't' | b'f' | b'n' | b'-' | b'0'..=b'9' => {
+                while self.pos < self.src.len()
+                    && matches!(self.src[self.pos], b'a'..=b'z' | b'0'..=b'9' | b'-' | b'+' | b'.' | b'E')
+                {
+                    self.pos += 1;
+                }
+            }
+            b => bail!("Unexpected `{}` at byte {}", b as char, self.pos),
+        }
+
+        self.spans.push(JsonSpan {
+            path: path.clone(),
+            value: start..self.pos,
+            member: member_start..self.pos,
+        });
+
+        Ok(())
+    }
+
+    /// Skips a string, including its quotes
+    fn string(&mut self) -> Result<()> {
+        self.expect(b'"')?;
+        loop {
+            match self.next()? {
+                b'\\' => {
+                    self.next()?;
+                }
+                b'"' => return Ok(()),
+                _ => {}
+            }
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.src.len() && self.src[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<()> {
+        let pos = self.pos;
+        match self.next()? {
+            b if b == expected => Ok(()),
+            b => bail!("Expected `{}` but found `{}` at byte {}", expected as char, b as char, pos),
+        }
+    }
+
+    fn peek(&self) -> Result<u8> {
+        self.src
+            .get(self.pos)
+            .copied()
+            .context("Unexpected end of the JSON document")
+    }
+
+    fn next(&mut self) -> Result<u8> {
+        let b = self.peek()?;
+        self.pos += 1;
+        Ok(b)
+    }
+}
+
+/// One HTTP message of a transcript, and where its body is
+#[derive(Debug)]
+struct HttpBody {
+    /// Range of the start line and the headers, without the blank line closing them
+    head: Range<usize>,
+    /// Ranges of the transcript holding the body, more than one when it is chunk encoded
+    segments: Vec<Range<usize>>,
+    event_stream: bool,
+}
+
+impl HttpBody {
+    /// The body with the chunked transfer encoding removed
+    fn content(&self, transcript: &[u8]) -> Vec<u8> {
+        self.segments
+            .iter()
+            .flat_map(|segment| transcript[segment.clone()].iter().copied())
+            .collect()
+    }
+
+    /// Maps a range of the decoded content back onto the transcript
+    fn map_range(&self, range: &Range<usize>) -> Vec<Range<usize>> {
+        let mut mapped = vec![];
+        let mut content_offset = 0;
+
+        for segment in &self.segments {
+            let segment_len = segment.len();
+            let start = range.start.max(content_offset);
+            let end = range.end.min(content_offset + segment_len);
+            if start < end {
+                mapped.push((segment.start + start - content_offset)..(segment.start + end - content_offset));
+            }
+            content_offset += segment_len;
+        }
+
+        mapped
+    }
+}
+
+/// Splits a transcript into its HTTP/1.1 messages and returns where their bodies are
+fn http_bodies(transcript: &[u8], is_response: bool) -> Vec<HttpBody> {
+    http_messages(transcript, is_response)
+        .into_iter()
+        .filter(|message| message.segments.iter().any(|segment| !segment.is_empty()))
+        .collect()
+}
+
+/// Ranges of the heads (start line and headers) of the HTTP/1.1 messages of a transcript
+pub(crate) fn http_heads(transcript: &[u8], is_response: bool) -> Vec<Range<usize>> {
+    http_messages(transcript, is_response)
+        .into_iter()
+        .map(|message| message.head)
+        .collect()
+}
+
+fn http_messages(transcript: &[u8], is_response: bool) -> Vec<HttpBody> {
+    let mut messages = vec![];
+    let mut pos = 0;
+
+    while let Some(headers_len) = find(&transcript[pos..], b"\r\n\r\n") {
+        let head_range = pos..pos + headers_len;
+        let head = String::from_utf8_lossy(&transcript[head_range.clone()]).to_ascii_lowercase();
+        pos += headers_len + 4;
+
+        let mut lines = head.split("\r\n");
+        let start_line = lines.next().unwrap_or_default();
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim(), value.trim()))
+            .collect::<Vec<_>>();
+        let header = |name: &str| headers.iter().find(|(n, _)| *n == name).map(|(_, v)| *v);
+
+        let status = is_response
+            .then(|| start_line.split(' ').nth(1).and_then(|s| s.parse::<u16>().ok()))
+            .flatten();
+        if matches!(status, Some(100..=199 | 204 | 304)) {
+            messages.push(HttpBody {
+                head: head_range,
+                segments: vec![],
+                event_stream: false,
+            });
+            continue;
+        }
+
+        let event_stream = header("content-type").is_some_and(|v| v.contains("text/event-stream"));
+
+        let segments = if header("transfer-encoding").is_some_and(|v| v.contains("chunked")) {
+            let (segments, end) = chunk_segments(transcript, pos);
+            pos = end;
+            segments
+        } else if let Some(length) = header("content-length").and_then(|v| v.parse::<usize>().ok()) {
+            let end = (pos + length).min(transcript.len());
+            let segment = pos..end;
+            pos = end;
+            vec![segment]
+        } else if is_response {
+            // Without a length the response is delimited by the connection closing
+            let segment = pos..transcript.len();
+            pos = transcript.len();
+            vec![segment]
+        } else {
+            vec![]
+        };
+
+        messages.push(HttpBody {
+            head: head_range,
+            segments,
+            event_stream,
+        });
+    }
+
+    messages
+}
+
+/// Returns the data ranges of a chunk encoded body starting at `pos`, and where the body ends
+fn chunk_segments(transcript: &[u8], mut pos: usize) -> (Vec<Range<usize>>, usize) {
+    let mut segments = vec![];
+
+    while let Some(line_len) = find(&transcript[pos..], b"\r\n") {
+        let size_line = String::from_utf8_lossy(&transcript[pos..pos + line_len]);
+        let size = size_line.split(';').next().unwrap_or_default().trim();
+        let Ok(size) = usize::from_str_radix(size, 16) else {
+            break;
+        };
+        pos += line_len + 2;
+
+        if size == 0 {
+            // Skip the trailers, up to the empty line closing the body
+            while let Some(line_len) = find(&transcript[pos..], b"\r\n") {
+                pos += line_len + 2;
+                if line_len == 0 {
+                    break;
+                }
+            }
+            return (segments, pos);
+        }
+
+        let end = (pos + size).min(transcript.len());
+        segments.push(pos..end);
+        pos = (end + 2).min(transcript.len());
+    }
+
+    (segments, pos)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat_params::ChatRequestParams;
+    use crate::config::{ModelApiSettings, ModelSettings};
+    use crate::model_interactions::chat_provider;
+    use serde_json::json;
+
+    fn redact(transcript: &[u8], ranges: &[Range<usize>]) -> String {
+        let mut redacted = transcript.to_vec();
+        for range in ranges {
+            redacted[range.clone()].fill(b'X');
+        }
+        String::from_utf8(redacted).unwrap()
+    }
+
+    #[test]
+    fn test_json_path_parsing() -> Result<()> {
+        assert_eq!(
+            JsonPath::parse("choices[0].message.content")?.segments,
+            vec![
+                PathSegment::Key("choices".to_string()),
+                PathSegment::Index(0),
+                PathSegment::Key("message".to_string()),
+                PathSegment::Key("content".to_string()),
+            ]
+        );
+        assert_eq!(
+            JsonPath::parse("messages[*].*")?.segments,
+            vec![
+                PathSegment::Key("messages".to_string()),
+                PathSegment::AnyIndex,
+                PathSegment::AnyKey,
+            ]
+        );
+        assert!(JsonPath::parse("messages[x]").is_err());
+        assert!(JsonPath::parse("messages..content").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_hide_request_fields() -> Result<()> {
+        let body = r#"{"model":"gpt-4o","messages":[{"role":"user","content":"hi im bob"}],"temperature":0.3}"#;
+        let transcript = format!(
+            "POST /v1/chat/completions HTTP/1.1\r\ncontent-length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+
+        let disclosure = BodyDisclosure::Hide(vec![JsonPath::parse("messages[*].content")?]);
+        let ranges = private_body_ranges(transcript.as_bytes(), &disclosure, false);
+
+        assert!(redact(transcript.as_bytes(), &ranges).ends_with(
+            r#"{"model":"gpt-4o","messages":[{"role":"user","content":XXXXXXXXXXX}],"temperature":0.3}"#
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_reveal_only_chunked_response() -> Result<()> {
+        let transcript = b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n\
+            f\r\n{\"id\":\"x\",\"choi\r\n\
+            16\r\nces\":[{\"message\":\"hi\"}\r\n\
+            2\r\n]}\r\n0\r\n\r\n";
+
+        let disclosure = BodyDisclosure::RevealOnly(vec![JsonPath::parse("choices[0].message")?]);
+        let ranges = private_body_ranges(transcript, &disclosure, true);
+
+        let redacted = redact(transcript, &ranges);
+        assert!(redacted.contains("\"message\":\"hi\""));
+        assert!(!redacted.contains("\"id\""));
+        // The chunk framing is not part of the body, so it stays untouched
+        assert!(redacted.contains("\r\n16\r\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_usage_stays_revealed() -> Result<()> {
+        let transcript = b"HTTP/1.1 200 OK\r\ncontent-length: 80\r\n\r\n\
+            {\"choices\":[{\"message\":\"hi\"}],\"usage\":{\"prompt_tokens\":5,\"completion_tokens\":2}}";
+        let usage = [JsonPath::parse("usage")?];
+
+        let disclosure = BodyDisclosure::RevealOnly(vec![JsonPath::parse("choices[0].message")?]).revealing(&usage);
+        let redacted = redact(transcript, &private_body_ranges(transcript, &disclosure, true));
+        assert!(redacted.contains("\"usage\":{\"prompt_tokens\":5,\"completion_tokens\":2}"));
+
+        let disclosure = BodyDisclosure::Hide(vec![JsonPath::parse("usage.prompt_tokens")?]).revealing(&usage);
+        assert_eq!(disclosure, BodyDisclosure::Hide(vec![]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_deeply_nested_body_is_redacted() -> Result<()> {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(json_spans(nested(MAX_JSON_DEPTH).as_bytes()).is_ok());
+        assert!(json_spans(nested(MAX_JSON_DEPTH + 2).as_bytes()).is_err());
+
+        let body = format!("{{\"model\":\"gpt-4o\",\"x\":{}}}", nested(100_000));
+        let transcript = format!("POST / HTTP/1.1\r\ncontent-length: {}\r\n\r\n{body}", body.len());
+        let disclosure = BodyDisclosure::RevealOnly(vec![JsonPath::parse("model")?]);
+        let ranges = private_body_ranges(transcript.as_bytes(), &disclosure, false);
+        assert!(!redact(transcript.as_bytes(), &ranges).contains("gpt-4o"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_system_prompt_only_per_provider() -> Result<()> {
+        let model_settings = ModelSettings::new("claude-3-5-sonnet".to_string(), ModelApiSettings::new(String::new()));
+        let messages = vec![
+            json!({ "role": "system", "content": "Answer in French." }),
+            json!({ "role": "user", "content": "hi im bob! and i live in sf" }),
+            json!({ "role": "assistant", "content": "Bonjour Bob !" }),
+            json!({ "role": "user", "content": "where do i live?" }),
+        ];
+
+        for provider in [ApiProvider::OpenAi, ApiProvider::Anthropic] {
+            let body = chat_provider(provider)
+                .request_body(&model_settings, messages.clone(), vec![], &ChatRequestParams::default(), false)?
+                .to_string();
+            let transcript = format!("POST /v1/messages HTTP/1.1\r\ncontent-length: {}\r\n\r\n{body}", body.len());
+
+            let disclosure = BodyDisclosure::system_prompt_only(provider);
+            let private_ranges = private_body_ranges(transcript.as_bytes(), &disclosure, false);
+            let redacted = redact(transcript.as_bytes(), &private_ranges);
+            assert!(redacted.contains("Answer in French."), "{provider:?}: {redacted}");
+            assert!(redacted.contains("claude-3-5-sonnet"), "{provider:?}: {redacted}");
+            for user_content in ["bob", "sf", "Bonjour", "where do i live"] {
+                assert!(!redacted.contains(user_content), "{provider:?}: {redacted}");
+            }
+
+            // The prompt sits in the prefix a verifier can read
+            let (body, revealed_len) = &http_body_revealed_prefixes(transcript.as_bytes(), &private_ranges, false)[0];
+            let prompt_paths: Vec<_> = revealed_prefix_values(body, *revealed_len)
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect();
+            let BodyDisclosure::RevealPrefix(prompt_path) = &disclosure else { unreachable!() };
+            assert!(prompt_paths.iter().any(|path| prompt_path.matches(path)), "{provider:?}: {redacted}");
+        }
+        Ok(())
+    }
+}
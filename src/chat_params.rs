@@ -0,0 +1,228 @@
+use crate::py_errors::InvalidInputError;
+use anyhow::{bail, Context, Result};
+use pyo3::{pyclass, pymethods, PyResult};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+/// Sampling and output parameters of a chat completion request, in the OpenAI format.
+///
+/// Unset parameters are left out of the request, so the API defaults apply.
+#[pyclass]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatRequestParams {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    /// `"auto"`, `"none"`, `"required"` or `{"type": "function", "function": {"name": ...}}`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<Value>,
+    /// e.g. `{"type": "json_object"}` or `{"type": "json_schema", "json_schema": {...}}`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+impl ChatRequestParams {
+    /// Checks the ranges the OpenAI API accepts, so a bad value fails before the MPC setup
+    pub fn validate(&self) -> Result<()> {
+        check_range("temperature", self.temperature, 0.0, 2.0)?;
+        check_range("top_p", self.top_p, 0.0, 1.0)?;
+        check_range("presence_penalty", self.presence_penalty, -2.0, 2.0)?;
+        check_range("frequency_penalty", self.frequency_penalty, -2.0, 2.0)?;
+
+        if self.max_tokens == Some(0) {
+            bail!("`max_tokens` must be at least 1");
+        }
+        if self.n == Some(0) {
+            bail!("`n` must be at least 1");
+        }
+        if let Some(stop) = &self.stop {
+            if stop.len() > 4 {
+                bail!("At most 4 stop sequences are supported, got {}", stop.len());
+            }
+            if stop.iter().any(String::is_empty) {
+                bail!("Stop sequences cannot be empty");
+            }
+        }
+        if let Some(tool_choice) = &self.tool_choice {
+            let valid = match tool_choice {
+                Value::String(choice) => ["auto", "none", "required"].contains(&choice.as_str()),
+                Value::Object(_) => tool_choice["function"]["name"].is_string(),
+                _ => false,
+            };
+            if !valid {
+                bail!("Invalid `tool_choice` {tool_choice}, expected `auto`, `none`, `required` or a function");
+            }
+        }
+        if let Some(response_format) = &self.response_format {
+            if !response_format["type"].is_string() {
+                bail!("Invalid `response_format` {response_format}, expected an object with a `type`");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds the set parameters to an OpenAI request body
+    pub(crate) fn insert_into(&self, json_body: &mut Map<String, Value>) -> Result<()> {
+        let Value::Object(params) = serde_json::to_value(self).context("Error serializing the request parameters")? else {
+            bail!("The request parameters are not a JSON object");
+        };
+        json_body.extend(params);
+        Ok(())
+    }
+}
+
+fn check_range(name: &str, value: Option<f64>, min: f64, max: f64) -> Result<()> {
+    match value {
+        Some(value) if !(min..=max).contains(&value) => {
+            bail!("`{name}` must be between {min} and {max}, got {value}")
+        }
+        _ => Ok(()),
+    }
+}
+
+#[pymethods]
+impl ChatRequestParams {
+    /// `tool_choice` is `"auto"`, `"none"`, `"required"`, the name of a function or a JSON object.
+    /// `response_format` is `"text"`, `"json_object"` or a JSON object.
+    #[new]
+    #[pyo3(signature = (
+        *,
+        temperature = None,
+        top_p = None,
+        max_tokens = None,
+        stop = None,
+        seed = None,
+        tool_choice = None,
+        response_format = None,
+        n = None,
+        presence_penalty = None,
+        frequency_penalty = None,
+        logprobs = None,
+        user = None
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn py_new(
+        temperature: Option<f64>,
+        top_p: Option<f64>,
+        max_tokens: Option<u64>,
+        stop: Option<Vec<String>>,
+        seed: Option<i64>,
+        tool_choice: Option<String>,
+        response_format: Option<String>,
+        n: Option<u32>,
+        presence_penalty: Option<f64>,
+        frequency_penalty: Option<f64>,
+        logprobs: Option<bool>,
+        user: Option<String>,
+    ) -> PyResult<Self> {
+        let params = parse_json_options(tool_choice, response_format).map(|(tool_choice, response_format)| Self {
+            temperature,
+            top_p,
+            max_tokens,
+            stop,
+            seed,
+            tool_choice,
+            response_format,
+            n,
+            presence_penalty,
+            frequency_penalty,
+            logprobs,
+            user,
+        });
+
+        params
+            .and_then(|params| params.validate().map(|()| params))
+            .context("Invalid request parameters")
+            .map_err(|e| InvalidInputError::new_err(format!("{e:#}")))
+    }
+
+    /// The set parameters, as the JSON added to the request body
+    fn to_json(&self) -> PyResult<String> {
+        serde_json::to_string(self).map_err(|e| InvalidInputError::new_err(e.to_string()))
+    }
+
+    fn __repr__(&self) -> String {
+        format!("ChatRequestParams({})", serde_json::to_string(self).unwrap_or_default())
+    }
+}
+
+fn parse_json_options(tool_choice: Option<String>, response_format: Option<String>) -> Result<(Option<Value>, Option<Value>)> {
+    let tool_choice = match tool_choice {
+        Some(choice) if choice.trim_start().starts_with('{') => {
+            Some(serde_json::from_str(&choice).context("Error parsing `tool_choice`")?)
+        }
+        Some(choice) if ["auto", "none", "required"].contains(&choice.as_str()) => Some(json!(choice)),
+        Some(function) => Some(json!({ "type": "function", "function": { "name": function } })),
+        None => None,
+    };
+
+    let response_format = match response_format {
+        Some(format) if format.trim_start().starts_with('{') => {
+            Some(serde_json::from_str(&format).context("Error parsing `response_format`")?)
+        }
+        Some(format) => Some(json!({ "type": format })),
+        None => None,
+    };
+
+    Ok((tool_choice, response_format))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unset_params_are_omitted() -> Result<()> {
+        let params = ChatRequestParams {
+            temperature: Some(0.3),
+            stop: Some(vec!["\n\n".to_string()]),
+            tool_choice: Some(json!("required")),
+            ..ChatRequestParams::default()
+        };
+
+        let mut json_body = Map::new();
+        params.insert_into(&mut json_body)?;
+        assert_eq!(
+            Value::Object(json_body),
+            json!({ "temperature": 0.3, "stop": ["\n\n"], "tool_choice": "required" })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(ChatRequestParams::default().validate().is_ok());
+
+        let invalid = [
+            ChatRequestParams { temperature: Some(2.5), ..Default::default() },
+            ChatRequestParams { top_p: Some(-0.1), ..Default::default() },
+            ChatRequestParams { frequency_penalty: Some(3.0), ..Default::default() },
+            ChatRequestParams { max_tokens: Some(0), ..Default::default() },
+            ChatRequestParams { stop: Some(vec!["a".to_string(); 5]), ..Default::default() },
+            ChatRequestParams { tool_choice: Some(json!("sometimes")), ..Default::default() },
+            ChatRequestParams { response_format: Some(json!("json")), ..Default::default() },
+        ];
+        for params in invalid {
+            assert!(params.validate().is_err(), "{params:?}");
+        }
+    }
+}
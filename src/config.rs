@@ -1,36 +1,345 @@
 #![allow(dead_code)]
 
-use std::sync::LazyLock;
+use crate::body_disclosure::{BodyDisclosure, JsonPath};
+use crate::model_interactions::chat_provider;
+use crate::py_errors::InvalidInputError;
+use crate::redaction::{HeaderRule, RedactionScope};
+use anyhow::{bail, Context, Result};
+use hyper::header::{
+    HeaderName, HeaderValue, ACCEPT_ENCODING, CONNECTION, CONTENT_LENGTH, CONTENT_TYPE, HOST, TRANSFER_ENCODING,
+};
+use pyo3::{pyclass, pymethods, PyResult};
+use rustls::{Certificate, RootCertStore};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use tls_core::anchors::{OwnedTrustAnchor, RootCertStore as ServerRootCertStore};
 
-static SETUP_PROMPT: LazyLock<&str> =
-    LazyLock::new(|| "Model Prompt: YOU ARE GOING TO BE ACTING AS A HELPFUL ASSISTANT");
+/// Headers set on every request, which `extra_headers` cannot set again: the request would carry both values
+const RESERVED_HEADERS: [HeaderName; 6] =
+    [HOST, CONNECTION, ACCEPT_ENCODING, CONTENT_TYPE, CONTENT_LENGTH, TRANSFER_ENCODING];
+
+/// Domain of the model API used when no other endpoint is configured
+pub const DEFAULT_SERVER_DOMAIN: &str = "api.red-pill.ai";
+
+/// Wire format spoken by the model API
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ApiProvider {
+    /// OpenAI `/v1/chat/completions` and the compatible APIs
+    #[default]
+    OpenAi,
+    /// Anthropic Messages API (`/v1/messages`)
+    Anthropic,
+}
+
+impl FromStr for ApiProvider {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "openai" => Ok(Self::OpenAi),
+            "anthropic" => Ok(Self::Anthropic),
+            _ => bail!("Unknown provider `{s}`, expected `openai` or `anthropic`"),
+        }
+    }
+}
+
+impl ApiProvider {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::OpenAi => "openai",
+            Self::Anthropic => "anthropic",
+        }
+    }
+
+    pub fn default_inference_route(&self) -> &'static str {
+        match self {
+            Self::OpenAi => "/v1/chat/completions",
+            Self::Anthropic => "/v1/messages",
+        }
+    }
+}
 
 /// Configuration for API settings, including server endpoints and the API key
-#[derive(Debug, Default)]
+#[pyclass]
+#[derive(Debug, Clone, Default)]
 pub struct ModelApiSettings {
-    pub server_domain: &'static str,
-    pub inference_route: &'static str,
-    pub model_list_route: &'static str,
+    #[pyo3(get)]
+    pub server_domain: String,
+    #[pyo3(get)]
+    pub port: u16,
+    #[pyo3(get)]
+    pub inference_route: String,
+    #[pyo3(get)]
+    pub model_list_route: String,
+    #[pyo3(get)]
+    pub extra_headers: Vec<(String, String)>,
+    pub provider: ApiProvider,
     pub api_key: String,
+    /// TCP address dialed instead of `server_domain:port`, the certificate is still checked against `server_domain`
+    #[pyo3(get)]
+    pub server_address: Option<String>,
+    /// PEM encoded root certificates trusted for the model API, in addition to the web roots
+    pub root_ca_pem: Option<String>,
 }
 
 impl ModelApiSettings {
-    pub(crate) fn new(api_key: String) -> Self {
+    pub fn new(api_key: String) -> Self {
         Self {
-            server_domain: "api.red-pill.ai",
-            inference_route: "/v1/chat/completions",
-            model_list_route: "/v1/models",
+            server_domain: DEFAULT_SERVER_DOMAIN.to_string(),
+            port: 443,
+            inference_route: ApiProvider::OpenAi.default_inference_route().to_string(),
+            model_list_route: "/v1/models".to_string(),
+            extra_headers: vec![],
+            provider: ApiProvider::OpenAi,
             api_key,
+            server_address: None,
+            root_ca_pem: None,
+        }
+    }
+
+    /// Address the prover opens the TCP connection to
+    pub fn connect_address(&self) -> String {
+        match &self.server_address {
+            Some(address) => address.clone(),
+            None => format!("{}:{}", self.server_domain, self.port),
+        }
+    }
+
+    /// The web roots and the custom root certificates, which the server certificate is checked against
+    pub fn root_cert_store(&self) -> Result<ServerRootCertStore> {
+        server_root_cert_store(self.root_ca_pem.as_deref())
+    }
+
+    /// Value of the `Host` header, which only carries the port when it is not the default one
+    pub fn host_header(&self) -> String {
+        if self.port == 443 {
+            self.server_domain.clone()
+        } else {
+            format!("{}:{}", self.server_domain, self.port)
         }
     }
+
+    /// Checks the endpoint can be notarised, i.e. the name the prover verifies
+    /// the server certificate against is the host the requests are addressed to.
+    pub fn validate(&self) -> Result<()> {
+        let domain = &self.server_domain;
+        if domain.is_empty() || domain.contains("://") || domain.contains(['/', ':', ' ']) {
+            bail!("Server domain `{domain}` must be a bare DNS name (no scheme, port or path)");
+        }
+        if domain.parse::<IpAddr>().is_ok() {
+            bail!("Server domain `{domain}` must be a DNS name, the notary cannot attest IP addresses");
+        }
+
+        for route in [&self.inference_route, &self.model_list_route] {
+            if !route.starts_with('/') {
+                bail!("Route `{route}` must start with `/`");
+            }
+        }
+
+        let auth_headers = chat_provider(self.provider).auth_headers("");
+        for (name, value) in &self.extra_headers {
+            let header_name = HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("Invalid header name `{name}`"))?;
+            HeaderValue::from_str(value)
+                .with_context(|| format!("Invalid value for header `{name}`"))?;
+
+            if RESERVED_HEADERS.contains(&header_name) || auth_headers.iter().any(|(auth, _)| *auth == header_name) {
+                bail!("Header `{name}` is set by the client and cannot be passed in the extra headers");
+            }
+        }
+        if let Some(address) = &self.server_address {
+            let host_and_port = address.rsplit_once(':').is_some_and(|(host, port)| {
+                !host.is_empty() && !host.contains(['/', ':', ' ']) && port.parse::<u16>().is_ok()
+            });
+            if !host_and_port && address.parse::<SocketAddr>().is_err() {
+                bail!("Server address `{address}` must be `host:port` (no scheme or path)");
+            }
+        }
+        if self.root_ca_pem.is_some() {
+            self.root_cert_store()?;
+        }
+
+        Ok(())
+    }
 }
 
-#[derive(Debug)]
+/// Trust anchors of the web PKI, extended with the PEM encoded `root_ca_pem`
+pub(crate) fn server_root_cert_store(root_ca_pem: Option<&str>) -> Result<ServerRootCertStore> {
+    let mut root_cert_store = ServerRootCertStore::empty();
+    root_cert_store.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+            anchor.subject.to_vec(),
+            anchor.subject_public_key_info.to_vec(),
+            anchor.name_constraints.as_ref().map(|constraints| constraints.to_vec()),
+        )
+    }));
+
+    let Some(root_ca_pem) = root_ca_pem else {
+        return Ok(root_cert_store);
+    };
+    let certificates = rustls_pemfile::certs(&mut root_ca_pem.as_bytes())
+        .context("Error reading the server root certificates")?;
+    if certificates.is_empty() {
+        bail!("No certificate found in the server root certificates");
+    }
+    for certificate in certificates {
+        root_cert_store
+            .add(&tls_core::key::Certificate(certificate))
+            .map_err(|e| anyhow::anyhow!("Error adding a server root certificate: {e:?}"))?;
+    }
+
+    Ok(root_cert_store)
+}
+
+#[pymethods]
+impl ModelApiSettings {
+    #[new]
+    #[pyo3(signature = (
+        server_domain = DEFAULT_SERVER_DOMAIN.to_string(),
+        port = 443,
+        inference_route = None,
+        model_list_route = "/v1/models".to_string(),
+        extra_headers = HashMap::new(),
+        provider = "openai",
+        server_address = None,
+        root_ca_pem = None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn py_new(
+        server_domain: String,
+        port: u16,
+        inference_route: Option<String>,
+        model_list_route: String,
+        extra_headers: HashMap<String, String>,
+        provider: &str,
+        server_address: Option<String>,
+        root_ca_pem: Option<String>,
+    ) -> PyResult<Self> {
+        let provider = provider
+            .parse::<ApiProvider>()
+            .map_err(|e| InvalidInputError::new_err(e.to_string()))?;
+        let inference_route =
+            inference_route.unwrap_or_else(|| provider.default_inference_route().to_string());
+
+        let mut extra_headers = extra_headers.into_iter().collect::<Vec<_>>();
+        extra_headers.sort();
+
+        let settings = Self {
+            server_domain,
+            port,
+            inference_route,
+            model_list_route,
+            extra_headers,
+            provider,
+            // The API key is passed separately, so it never ends up in a Python object
+            api_key: String::new(),
+            server_address,
+            root_ca_pem,
+        };
+
+        settings
+            .validate()
+            .map_err(|e| InvalidInputError::new_err(format!("{e:#}")))?;
+
+        Ok(settings)
+    }
+
+    #[getter(provider)]
+    fn py_provider(&self) -> &'static str {
+        self.provider.as_str()
+    }
+}
+
+/// Default cap on the bytes the prover sends to the model API in one session
+pub const DEFAULT_MAX_SENT_DATA: usize = 1 << 12;
+/// Default cap on the bytes the prover receives from the model API in one session
+pub const DEFAULT_MAX_RECV_DATA: usize = 1 << 14;
+
+/// Protocol spoken by a remote notary
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NotaryProtocol {
+    /// HTTP API of the TLSNotary `notary-server`
+    #[default]
+    NotaryServer,
+    /// Plain TCP session of the `tlsn-langchain-notary` service
+    Tcp,
+    /// WebSocket session of the `tlsn-langchain-notary` service
+    WebSocket,
+}
+
+impl FromStr for NotaryProtocol {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "notary-server" => Ok(Self::NotaryServer),
+            "tcp" => Ok(Self::Tcp),
+            "websocket" => Ok(Self::WebSocket),
+            _ => bail!("Unknown notary protocol `{s}`, expected `notary-server`, `tcp` or `websocket`"),
+        }
+    }
+}
+
+impl NotaryProtocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NotaryServer => "notary-server",
+            Self::Tcp => "tcp",
+            Self::WebSocket => "websocket",
+        }
+    }
+}
+
+/// Signing key of the in-process dummy notary
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum NotaryKey {
+    /// A fresh P-256 key, generated once per process
+    #[default]
+    Ephemeral,
+    /// PKCS#8 PEM file
+    Path(PathBuf),
+    /// PKCS#8 PEM string
+    Pem(String),
+    /// Environment variable holding a PKCS#8 PEM string
+    Env(String),
+    /// The key bundled in `tlsn/notary.key`. Its private part is published, so it is only for tests
+    /// and needs [`NotarySettings::allow_insecure_test_key`]
+    InsecureTestKey,
+}
+
+/// Configuration of the notary, either an in-process dummy notary or a remote notary server
+#[pyclass]
+#[derive(Debug, Clone)]
 pub struct NotarySettings {
-    pub dummy_notary: bool, // TODO - improve this struct to be more effective
-    pub host: &'static str,
+    #[pyo3(get)]
+    pub dummy_notary: bool,
+    #[pyo3(get)]
+    pub host: String,
+    #[pyo3(get)]
     pub port: u16,
-    pub path: &'static str,
+    pub protocol: NotaryProtocol,
+    /// Path prefix of the notary server API
+    #[pyo3(get)]
+    pub path: String,
+    #[pyo3(get)]
+    pub tls: bool,
+    /// PEM encoded root certificates trusted for the notary TLS connection, instead of the web roots
+    pub root_ca_pem: Option<String>,
+    /// API key of notary servers with an authorization whitelist
+    pub api_key: Option<String>,
+    #[pyo3(get)]
+    pub max_sent_data: usize,
+    #[pyo3(get)]
+    pub max_recv_data: usize,
+    /// Signing key of the dummy notary
+    pub notary_key: NotaryKey,
+    /// Allows the dummy notary to sign with the published test key
+    pub allow_insecure_test_key: bool,
+    /// Notarise the session when the model API answers with an error,
+    /// to prove that the provider refused or failed the request
+    pub prove_error_responses: bool,
 }
 
 /// Configuration for Notary settings, defining host, port, and path
@@ -38,25 +347,381 @@ impl Default for NotarySettings {
     fn default() -> Self {
         NotarySettings {
             dummy_notary: true,
-            host: "notary.pse.dev", // TODO - figure out why this is not working
+            host: "notary.pse.dev".to_string(),
             port: 443,
-            path: "v0.1.0-alpha.6",
+            protocol: NotaryProtocol::NotaryServer,
+            path: "v0.1.0-alpha.6".to_string(),
+            tls: true,
+            root_ca_pem: None,
+            api_key: None,
+            max_sent_data: DEFAULT_MAX_SENT_DATA,
+            max_recv_data: DEFAULT_MAX_RECV_DATA,
+            notary_key: NotaryKey::Ephemeral,
+            allow_insecure_test_key: false,
+            prove_error_responses: false,
+        }
+    }
+}
+
+impl NotarySettings {
+    /// A remote notary server reachable at `host:port`
+    pub fn remote(host: String, port: u16) -> Self {
+        Self {
+            dummy_notary: false,
+            host,
+            port,
+            path: String::new(),
+            ..Self::default()
+        }
+    }
+
+    /// Parses the custom root certificates, `None` meaning the web roots are trusted
+    pub fn root_cert_store(&self) -> Result<Option<RootCertStore>> {
+        let Some(root_ca_pem) = &self.root_ca_pem else {
+            return Ok(None);
+        };
+
+        let certificates = rustls_pemfile::certs(&mut root_ca_pem.as_bytes())
+            .context("Error reading the notary root certificates")?;
+        if certificates.is_empty() {
+            bail!("No certificate found in the notary root certificates");
+        }
+
+        let mut root_cert_store = RootCertStore::empty();
+        for certificate in certificates {
+            root_cert_store
+                .add(&Certificate(certificate))
+                .context("Error adding a notary root certificate")?;
+        }
+        Ok(Some(root_cert_store))
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.max_sent_data == 0 || self.max_recv_data == 0 {
+            bail!("The notarised data limits must not be zero");
+        }
+        if self.dummy_notary {
+            if self.notary_key == NotaryKey::InsecureTestKey && !self.allow_insecure_test_key {
+                bail!("The bundled notary test key is published and needs an explicit insecure opt-in");
+            }
+            return Ok(());
+        }
+
+        let host = &self.host;
+        if host.is_empty() || host.contains("://") || host.contains(['/', ':', ' ']) {
+            bail!("Notary host `{host}` must be a bare host name (no scheme, port or path)");
+        }
+        if self.root_ca_pem.is_some() && !self.tls {
+            bail!("Notary root certificates are only used with TLS enabled");
         }
+        if self.protocol != NotaryProtocol::NotaryServer && (self.tls || self.api_key.is_some()) {
+            bail!(
+                "The `{}` notary protocol supports neither TLS nor API keys",
+                self.protocol.as_str()
+            );
+        }
+        self.root_cert_store()?;
+
+        Ok(())
+    }
+}
+
+#[pymethods]
+impl NotarySettings {
+    /// A remote notary server
+    #[new]
+    #[pyo3(signature = (
+        host,
+        port = 443,
+        path = String::new(),
+        tls = None,
+        root_ca_pem = None,
+        api_key = None,
+        max_sent_data = DEFAULT_MAX_SENT_DATA,
+        max_recv_data = DEFAULT_MAX_RECV_DATA,
+        protocol = "notary-server",
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn py_new(
+        host: String,
+        port: u16,
+        path: String,
+        tls: Option<bool>,
+        root_ca_pem: Option<String>,
+        api_key: Option<String>,
+        max_sent_data: usize,
+        max_recv_data: usize,
+        protocol: &str,
+    ) -> PyResult<Self> {
+        let protocol = protocol
+            .parse::<NotaryProtocol>()
+            .map_err(|e| InvalidInputError::new_err(e.to_string()))?;
+
+        let settings = Self {
+            protocol,
+            path,
+            // Only the notary server API is served over TLS
+            tls: tls.unwrap_or(protocol == NotaryProtocol::NotaryServer),
+            root_ca_pem,
+            api_key,
+            max_sent_data,
+            max_recv_data,
+            ..Self::remote(host, port)
+        };
+
+        settings
+            .validate()
+            .map_err(|e| InvalidInputError::new_err(format!("{e:#}")))?;
+
+        Ok(settings)
+    }
+
+    #[getter(protocol)]
+    fn py_protocol(&self) -> &'static str {
+        self.protocol.as_str()
+    }
+
+    /// The in-process dummy notary.
+    ///
+    /// It signs with a fresh key unless one of `key_path`, `key_pem` or `key_env` is given.
+    /// `insecure_test_key` signs with the published key bundled with the package, for tests only.
+    #[staticmethod]
+    #[pyo3(signature = (
+        max_sent_data = DEFAULT_MAX_SENT_DATA,
+        max_recv_data = DEFAULT_MAX_RECV_DATA,
+        key_path = None,
+        key_pem = None,
+        key_env = None,
+        insecure_test_key = false,
+    ))]
+    fn dummy(
+        max_sent_data: usize,
+        max_recv_data: usize,
+        key_path: Option<PathBuf>,
+        key_pem: Option<String>,
+        key_env: Option<String>,
+        insecure_test_key: bool,
+    ) -> PyResult<Self> {
+        let notary_key = match (key_path, key_pem, key_env, insecure_test_key) {
+            (None, None, None, false) => NotaryKey::Ephemeral,
+            (Some(path), None, None, false) => NotaryKey::Path(path),
+            (None, Some(pem), None, false) => NotaryKey::Pem(pem),
+            (None, None, Some(var), false) => NotaryKey::Env(var),
+            (None, None, None, true) => NotaryKey::InsecureTestKey,
+            _ => {
+                return Err(InvalidInputError::new_err(
+                    "Only one of `key_path`, `key_pem`, `key_env` and `insecure_test_key` can be set",
+                ))
+            }
+        };
+
+        let settings = Self {
+            max_sent_data,
+            max_recv_data,
+            notary_key,
+            allow_insecure_test_key: insecure_test_key,
+            ..Self::default()
+        };
+
+        settings
+            .validate()
+            .map_err(|e| InvalidInputError::new_err(format!("{e:#}")))?;
+
+        Ok(settings)
     }
 }
 
-/// Privacy settings including topics to censor in requests and responses
-#[derive(Debug)]
+/// Where the token usage sits in the responses: the body or `message_delta` events, and the
+/// `message_start` events of the Anthropic streams
+const USAGE_PATHS: [&str; 2] = ["usage", "message.usage"];
+
+/// Privacy settings: the headers redacted in requests and responses, the secrets redacted anywhere
+/// and the disclosure rules of the bodies
+#[derive(Debug, Clone)]
 pub struct PrivacySettings {
-    pub request_topics_to_censor: &'static [&'static str],
-    pub response_topics_to_censor: &'static [&'static str],
+    pub request_header_rules: Vec<HeaderRule>,
+    pub response_header_rules: Vec<HeaderRule>,
+    /// Redacted wherever they appear in the transcripts, on top of the API key and the redacted header values
+    pub secrets: Vec<String>,
+    pub request_body_disclosure: BodyDisclosure,
+    pub response_body_disclosure: BodyDisclosure,
+}
+
+impl PrivacySettings {
+    /// Default header censoring, with the given rules for the request and response bodies.
+    ///
+    /// The token usage of the responses stays revealed, so the token counts can be attested.
+    pub fn with_body_disclosure(
+        request_body_disclosure: BodyDisclosure,
+        response_body_disclosure: BodyDisclosure,
+    ) -> Self {
+        let usage_paths = USAGE_PATHS
+            .iter()
+            .map(|path| JsonPath::parse(path))
+            .collect::<Result<Vec<_>>>()
+            .expect("The usage paths are valid");
+
+        Self {
+            request_body_disclosure,
+            response_body_disclosure: response_body_disclosure.revealing(&usage_paths),
+            ..Self::default()
+        }
+    }
+}
+
+/// Headers to redact in the proof, on top of the body disclosure rules.
+///
+/// Header patterns ignore case: `re:<regex>` is a regular expression, a pattern with `*` or `?` a glob and
+/// anything else a header name. `request_headers` and `response_headers` replace the default lists and only
+/// redact the values, the `*_header_lines` ones redact the whole lines, names included. `secrets` are
+/// redacted wherever they appear in the request or the response, however short they are.
+#[pyclass]
+#[derive(Debug, Clone)]
+pub struct RedactionPolicy {
+    pub request_header_rules: Vec<HeaderRule>,
+    pub response_header_rules: Vec<HeaderRule>,
+    pub secrets: Vec<String>,
+}
+
+#[pymethods]
+impl RedactionPolicy {
+    #[new]
+    #[pyo3(signature = (
+        request_headers = None,
+        response_headers = None,
+        request_header_lines = vec![],
+        response_header_lines = vec![],
+        secrets = vec![]
+    ))]
+    fn py_new(
+        request_headers: Option<Vec<String>>,
+        response_headers: Option<Vec<String>>,
+        request_header_lines: Vec<String>,
+        response_header_lines: Vec<String>,
+        secrets: Vec<String>,
+    ) -> PyResult<Self> {
+        let defaults = PrivacySettings::default();
+        let rules = |values: Option<Vec<String>>, lines: Vec<String>, default: Vec<HeaderRule>| -> Result<Vec<HeaderRule>> {
+            let mut rules = lines
+                .iter()
+                .map(|pattern| HeaderRule::parse(pattern, RedactionScope::Line))
+                .collect::<Result<Vec<_>>>()?;
+            match values {
+                Some(values) => {
+                    for pattern in &values {
+                        rules.push(HeaderRule::parse(pattern, RedactionScope::Value)?);
+                    }
+                }
+                None => rules.extend(default),
+            }
+            Ok(rules)
+        };
+
+        let request = rules(request_headers, request_header_lines, defaults.request_header_rules)
+            .context("Invalid request header rules");
+        let response = rules(response_headers, response_header_lines, defaults.response_header_rules)
+            .context("Invalid response header rules");
+
+        // An empty secret matches nothing, the caller most likely passed an unset variable
+        if secrets.iter().any(|secret| secret.trim().is_empty()) {
+            return Err(InvalidInputError::new_err("Empty secrets cannot be redacted"));
+        }
+
+        match (request, response) {
+            (Ok(request_header_rules), Ok(response_header_rules)) => Ok(Self {
+                request_header_rules,
+                response_header_rules,
+                secrets,
+            }),
+            (Err(e), _) | (_, Err(e)) => Err(InvalidInputError::new_err(format!("{e:#}"))),
+        }
+    }
+}
+
+/// Rules for revealing parts of the JSON bodies in the proof, as JSON paths such as
+/// `messages[*].content` or `choices[0].message.content`.
+///
+/// For each direction either `hidden` paths are redacted, or only the `revealed` ones are kept.
+/// With `reveal_system_prompt_only`, the request only reveals the model and the system prompt.
+#[pyclass]
+#[derive(Debug, Clone, Default)]
+pub struct DisclosurePolicy {
+    /// Reveals nothing when `system_prompt_only` is set, see [`DisclosurePolicy::request_disclosure`]
+    pub request: BodyDisclosure,
+    pub response: BodyDisclosure,
+    /// The request disclosure is [`BodyDisclosure::system_prompt_only`], which needs a setup prompt
+    pub system_prompt_only: bool,
+}
+
+impl DisclosurePolicy {
+    /// The request disclosure for the requests of `provider`, which places the system prompt
+    pub fn request_disclosure(&self, provider: ApiProvider) -> BodyDisclosure {
+        if self.system_prompt_only {
+            BodyDisclosure::system_prompt_only(provider)
+        } else {
+            self.request.clone()
+        }
+    }
+}
+
+#[pymethods]
+impl DisclosurePolicy {
+    #[new]
+    #[pyo3(signature = (request_hidden = None, request_revealed = None, response_hidden = None, response_revealed = None, reveal_system_prompt_only = false))]
+    fn py_new(
+        request_hidden: Option<Vec<String>>,
+        request_revealed: Option<Vec<String>>,
+        response_hidden: Option<Vec<String>>,
+        response_revealed: Option<Vec<String>>,
+        reveal_system_prompt_only: bool,
+    ) -> PyResult<Self> {
+        let request = if reveal_system_prompt_only {
+            match (&request_hidden, &request_revealed) {
+                // Resolved once the provider is known
+                (None, None) => Ok(BodyDisclosure::RevealOnly(vec![])),
+                _ => Err(anyhow::anyhow!("`reveal_system_prompt_only` cannot be combined with request paths")),
+            }
+        } else {
+            body_disclosure(request_hidden, request_revealed)
+        }
+        .context("Invalid request disclosure rules");
+        let response = body_disclosure(response_hidden, response_revealed)
+            .context("Invalid response disclosure rules");
+
+        match (request, response) {
+            (Ok(request), Ok(response)) => Ok(Self {
+                request,
+                response,
+                system_prompt_only: reveal_system_prompt_only,
+            }),
+            (Err(e), _) | (_, Err(e)) => Err(InvalidInputError::new_err(format!("{e:#}"))),
+        }
+    }
+}
+
+fn body_disclosure(hidden: Option<Vec<String>>, revealed: Option<Vec<String>>) -> Result<BodyDisclosure> {
+    let parse = |paths: Vec<String>| {
+        paths
+            .iter()
+            .map(|path| JsonPath::parse(path))
+            .collect::<Result<Vec<JsonPath>>>()
+    };
+
+    match (hidden, revealed) {
+        (Some(_), Some(_)) => bail!("Only one of the hidden and revealed paths can be set"),
+        (Some(hidden), None) => Ok(BodyDisclosure::Hide(parse(hidden)?)),
+        (None, Some(revealed)) => Ok(BodyDisclosure::RevealOnly(parse(revealed)?)),
+        (None, None) => Ok(BodyDisclosure::RevealAll),
+    }
 }
 
 impl Default for PrivacySettings {
     fn default() -> Self {
         Self {
-            request_topics_to_censor: &["authorization"],
-            response_topics_to_censor: &[
+            request_header_rules: ["authorization", "proxy-authorization", "x-api-key", "api-key", "cookie"]
+                .map(HeaderRule::value_of)
+                .to_vec(),
+            response_header_rules: [
                 "anthropic-ratelimit-requests-reset",
                 "anthropic-ratelimit-tokens-reset",
                 "request-id",
@@ -64,31 +729,41 @@ impl Default for PrivacySettings {
                 "cf-ray",
                 "server-timing",
                 "report-to",
-            ],
+                "set-cookie",
+            ]
+            .map(HeaderRule::value_of)
+            .to_vec(),
+            secrets: vec![],
+            request_body_disclosure: BodyDisclosure::RevealAll,
+            response_body_disclosure: BodyDisclosure::RevealAll,
         }
     }
 }
 
 /// Model settings including API settings, model ID, and setup prompt
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ModelSettings {
     pub api_settings: ModelApiSettings,
     pub id: String,
-    pub setup_prompt: &'static str,
+    /// Sent as a `system` message ahead of the conversation of every request
+    pub setup_prompt: Option<String>,
+    /// Checks the model against the model list of the API before the inference request
+    pub validate_model: bool,
 }
 
 impl ModelSettings {
-    fn new(model_id: String, api_settings: ModelApiSettings) -> Self {
+    pub fn new(model_id: String, api_settings: ModelApiSettings) -> Self {
         Self {
             api_settings,
             id: model_id,
-            setup_prompt: *SETUP_PROMPT,
+            setup_prompt: None,
+            validate_model: false,
         }
     }
 }
 
 /// Complete application configuration including model, privacy, and notary settings
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Config {
     pub model_settings: ModelSettings,
     pub privacy_settings: PrivacySettings,
@@ -104,3 +779,57 @@ impl Config {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_api_settings_validation() {
+        let mut settings = ModelApiSettings::new("key".to_string());
+        assert!(settings.validate().is_ok());
+
+        settings.server_domain = "https://api.openai.com".to_string();
+        assert!(settings.validate().is_err());
+
+        settings.server_domain = "127.0.0.1".to_string();
+        assert!(settings.validate().is_err());
+
+        settings.server_domain = "localhost".to_string();
+        settings.port = 8443;
+        settings.extra_headers = vec![("X-Title".to_string(), "my-agent".to_string())];
+        assert!(settings.validate().is_ok());
+
+        // Even a matching value would be sent next to the one of the client
+        for (name, value) in [("Host", "localhost:8443"), ("host", "api.openai.com"), ("Authorization", "Bearer x")] {
+            settings.extra_headers = vec![(name.to_string(), value.to_string())];
+            assert!(settings.validate().is_err(), "{name}");
+        }
+
+        settings.provider = ApiProvider::Anthropic;
+        settings.extra_headers = vec![("anthropic-version".to_string(), "2024-01-01".to_string())];
+        assert!(settings.validate().is_err());
+        settings.extra_headers = vec![("Authorization".to_string(), "Bearer x".to_string())];
+        assert!(settings.validate().is_ok());
+    }
+
+    #[test]
+    fn test_server_address_and_root_ca() {
+        let mut settings = ModelApiSettings::new("key".to_string());
+        assert_eq!(settings.connect_address(), "api.red-pill.ai:443");
+
+        for address in ["10.0.0.7:8443", "[::1]:8443", "gateway.internal:443"] {
+            settings.server_address = Some(address.to_string());
+            assert!(settings.validate().is_ok(), "{address}");
+            assert_eq!(settings.connect_address(), address);
+        }
+        for address in ["10.0.0.7", "https://gateway.internal:443", "gateway.internal:port"] {
+            settings.server_address = Some(address.to_string());
+            assert!(settings.validate().is_err(), "{address}");
+        }
+
+        settings.server_address = None;
+        settings.root_ca_pem = Some("not a certificate".to_string());
+        assert!(settings.validate().is_err());
+    }
+}
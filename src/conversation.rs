@@ -0,0 +1,338 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+
+/// Message of a chat conversation, in the OpenAI format.
+///
+/// Fields outside the schema are dropped when the message is parsed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "role", rename_all = "lowercase")]
+pub enum ChatMessage {
+    System {
+        content: MessageContent,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+    },
+    /// System instructions of the reasoning models
+    Developer {
+        content: MessageContent,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+    },
+    User {
+        content: MessageContent,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+    },
+    Assistant {
+        /// `None` when the message only calls tools
+        #[serde(default)]
+        content: Option<MessageContent>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        tool_calls: Vec<ToolCall>,
+    },
+    /// Result of the tool call `tool_call_id` of a preceding assistant message
+    Tool {
+        content: MessageContent,
+        tool_call_id: String,
+    },
+}
+
+impl ChatMessage {
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::System {
+            content: MessageContent::Text(content.into()),
+            name: None,
+        }
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::User {
+            content: MessageContent::Text(content.into()),
+            name: None,
+        }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::Assistant {
+            content: Some(MessageContent::Text(content.into())),
+            name: None,
+            tool_calls: vec![],
+        }
+    }
+
+    pub fn tool(tool_call_id: impl Into<String>, content: impl Into<String>) -> Self {
+        Self::Tool {
+            content: MessageContent::Text(content.into()),
+            tool_call_id: tool_call_id.into(),
+        }
+    }
+
+    pub fn role(&self) -> &'static str {
+        match self {
+            Self::System { .. } => "system",
+            Self::Developer { .. } => "developer",
+            Self::User { .. } => "user",
+            Self::Assistant { .. } => "assistant",
+            Self::Tool { .. } => "tool",
+        }
+    }
+
+    fn content(&self) -> Option<&MessageContent> {
+        match self {
+            Self::System { content, .. }
+            | Self::Developer { content, .. }
+            | Self::User { content, .. }
+            | Self::Tool { content, .. } => Some(content),
+            Self::Assistant { content, .. } => content.as_ref(),
+        }
+    }
+}
+
+/// A string, or a list of parts mixing text with images and audio
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+    InputAudio { input_audio: InputAudio },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageUrl {
+    /// URL or `data:` URI of the image
+    pub url: String,
+    /// `auto`, `low` or `high`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputAudio {
+    /// Base64 encoded audio
+    pub data: String,
+    /// `wav` or `mp3`
+    pub format: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolType {
+    #[default]
+    Function,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type", default)]
+    pub kind: ToolType,
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    /// JSON encoded arguments
+    pub arguments: String,
+}
+
+/// Tool offered to the model, in the OpenAI format
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolDefinition {
+    #[serde(rename = "type", default)]
+    pub kind: ToolType,
+    pub function: FunctionDefinition,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionDefinition {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// JSON schema of the arguments
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+/// Rejects the conversations the model API would reject, before the session is set up.
+///
+/// Every tool call of an assistant message must be answered by the tool messages that follow it,
+/// and every tool message must answer such a call.
+pub fn validate_conversation(messages: &[ChatMessage], tools: &[ToolDefinition]) -> Result<()> {
+    let mut tool_names = HashSet::new();
+    for (index, tool) in tools.iter().enumerate() {
+        let name = &tool.function.name;
+        check_function_name(name).with_context(|| format!("Invalid tool at index {index}"))?;
+        if !tool_names.insert(name.as_str()) {
+            bail!("Invalid tool at index {index}: the function name `{name}` is already used");
+        }
+        if tool.function.parameters.as_ref().is_some_and(|parameters| !parameters.is_object()) {
+            bail!("Invalid tool at index {index}: `function.parameters` must be a JSON schema object");
+        }
+    }
+
+    if messages.is_empty() {
+        bail!("The conversation has no message");
+    }
+
+    // Tool calls of the last assistant message not answered yet
+    let mut pending: Vec<&str> = vec![];
+    for (index, message) in messages.iter().enumerate() {
+        check_message(message).with_context(|| format!("Invalid message at index {index}"))?;
+
+        if let ChatMessage::Tool { tool_call_id, .. } = message {
+            let Some(position) = pending.iter().position(|id| id == tool_call_id) else {
+                bail!(
+                    "Invalid message at index {index}: no preceding assistant message made the tool call `{tool_call_id}`"
+                );
+            };
+            pending.remove(position);
+            continue;
+        }
+
+        if !pending.is_empty() {
+            bail!(
+                "Invalid message at index {index}: the tool calls `{}` are not answered before it",
+                pending.join("`, `")
+            );
+        }
+        if let ChatMessage::Assistant { tool_calls, .. } = message {
+            pending = tool_calls.iter().map(|tool_call| tool_call.id.as_str()).collect();
+        }
+    }
+
+    if !pending.is_empty() {
+        bail!(
+            "The tool calls `{}` of the last assistant message are not answered",
+            pending.join("`, `")
+        );
+    }
+
+    Ok(())
+}
+
+fn check_message(message: &ChatMessage) -> Result<()> {
+    if let ChatMessage::Assistant { content, tool_calls, .. } = message {
+        if content.is_none() && tool_calls.is_empty() {
+            bail!("An assistant message needs a `content` or `tool_calls`");
+        }
+
+        let mut ids = HashSet::new();
+        for (index, tool_call) in tool_calls.iter().enumerate() {
+            if !ids.insert(tool_call.id.as_str()) {
+                bail!("The id of `tool_calls[{index}]` is already used");
+            }
+            check_function_name(&tool_call.function.name)
+                .with_context(|| format!("Invalid field `tool_calls[{index}]`"))?;
+            serde_json::from_str::<Value>(&tool_call.function.arguments)
+                .with_context(|| format!("The arguments of `tool_calls[{index}]` are not valid JSON"))?;
+        }
+    }
+
+    if let Some(MessageContent::Parts(parts)) = message.content() {
+        if parts.is_empty() {
+            bail!("The `content` parts are empty");
+        }
+        let media = parts.iter().any(|part| !matches!(part, ContentPart::Text { .. }));
+        if media && !matches!(message, ChatMessage::User { .. }) {
+            bail!("Only user messages can hold images or audio, not {} messages", message.role());
+        }
+    }
+
+    Ok(())
+}
+
+/// OpenAI function names are 1 to 64 letters, digits, `_` or `-`
+fn check_function_name(name: &str) -> Result<()> {
+    let valid = (1..=64).contains(&name.len())
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-');
+    if !valid {
+        bail!("Invalid function name `{name}`, expected 1 to 64 letters, digits, `_` or `-`");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn weather_tool() -> ToolDefinition {
+        serde_json::from_value(json!({
+            "type": "function",
+            "function": { "name": "get_weather", "parameters": { "type": "object" } },
+        }))
+        .unwrap()
+    }
+
+    fn tool_call_message(ids: &[&str]) -> ChatMessage {
+        let tool_calls = ids
+            .iter()
+            .map(|id| json!({ "id": id, "type": "function", "function": { "name": "get_weather", "arguments": "{}" } }))
+            .collect::<Vec<_>>();
+        serde_json::from_value(json!({ "role": "assistant", "content": null, "tool_calls": tool_calls })).unwrap()
+    }
+
+    #[test]
+    fn test_messages_keep_the_openai_shape() -> Result<()> {
+        let messages = json!([
+            { "role": "system", "content": "Be brief." },
+            { "role": "user", "content": [
+                { "type": "text", "text": "What is this?" },
+                { "type": "image_url", "image_url": { "url": "data:image/png;base64,AAAA" } },
+            ] },
+            { "role": "assistant", "content": null, "tool_calls": [
+                { "id": "call_1", "type": "function", "function": { "name": "lookup", "arguments": "{\"q\":\"png\"}" } },
+            ] },
+            { "role": "tool", "content": "An image", "tool_call_id": "call_1" },
+        ]);
+
+        let parsed: Vec<ChatMessage> = serde_json::from_value(messages.clone())?;
+        assert_eq!(parsed[3], ChatMessage::tool("call_1", "An image"));
+        assert_eq!(serde_json::to_value(&parsed)?, messages);
+
+        let error = serde_json::from_value::<ChatMessage>(json!({ "role": "tool", "content": "sunny" })).unwrap_err();
+        assert!(error.to_string().contains("tool_call_id"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_tool_calls_must_be_answered() -> Result<()> {
+        let tools = [weather_tool()];
+        let answered = [
+            ChatMessage::user("Weather in SF and LA?"),
+            tool_call_message(&["call_1", "call_2"]),
+            ChatMessage::tool("call_2", "rainy"),
+            ChatMessage::tool("call_1", "sunny"),
+            ChatMessage::assistant("Sunny in SF, rainy in LA."),
+        ];
+        validate_conversation(&answered, &tools)?;
+
+        let invalid: [&[ChatMessage]; 4] = [
+            &[ChatMessage::user("hi"), ChatMessage::tool("call_1", "sunny")],
+            &[tool_call_message(&["call_1"]), ChatMessage::user("hi")],
+            &[ChatMessage::user("hi"), tool_call_message(&["call_1"])],
+            &[ChatMessage::user("hi"), tool_call_message(&["call_1", "call_1"])],
+        ];
+        for messages in invalid {
+            assert!(validate_conversation(messages, &tools).is_err(), "{messages:?}");
+        }
+
+        assert!(validate_conversation(&[], &tools).is_err());
+        assert!(validate_conversation(&answered, &[weather_tool(), weather_tool()]).is_err());
+        Ok(())
+    }
+}
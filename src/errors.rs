@@ -0,0 +1,164 @@
+use crate::proof_verification::Proof;
+use hyper::header::RETRY_AFTER;
+use hyper::{HeaderMap, StatusCode};
+use thiserror::Error;
+
+/// Stage of a notarised request an error happened in.
+///
+/// Attached as context to errors, so callers (e.g. the Python bindings) can tell them apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum ErrorStage {
+    #[error("Invalid input")]
+    InvalidInput,
+    #[error("Error connecting to the notary")]
+    NotaryConnection,
+    #[error("Error connecting to the model API")]
+    ServerConnection,
+    #[error("Error exchanging with the model API")]
+    ModelRequest,
+    #[error("Error notarising the session")]
+    Notarisation,
+    #[error("Error building the proof")]
+    ProofBuild,
+    #[error("Error verifying the proof")]
+    ProofVerification,
+}
+
+impl ErrorStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InvalidInput => "invalid_input",
+            Self::NotaryConnection => "notary_connection",
+            Self::ServerConnection => "server_connection",
+            Self::ModelRequest => "model_request",
+            Self::Notarisation => "notarisation",
+            Self::ProofBuild => "proof_build",
+            Self::ProofVerification => "proof_verification",
+        }
+    }
+}
+
+/// Reason a proof failed verification, attached as context to the verification errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum VerificationFailure {
+    #[error("Malformed proof")]
+    MalformedProof,
+    #[error("Invalid notary signature or server certificate")]
+    InvalidSignature,
+    #[error("Proof for another server")]
+    WrongServer,
+}
+
+impl VerificationFailure {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::MalformedProof => "malformed_proof",
+            Self::InvalidSignature => "invalid_signature",
+            Self::WrongServer => "wrong_server",
+        }
+    }
+}
+
+/// Non-success response of the model API
+#[derive(Debug, Clone, Error)]
+pub enum ModelApiError {
+    #[error("Model API rate limited the request (status {status}): {body}")]
+    RateLimited {
+        status: u16,
+        body: String,
+        /// Seconds to wait before retrying, from the `Retry-After` header
+        retry_after: Option<u64>,
+    },
+    #[error("Model API rejected the credentials (status {status}): {body}")]
+    AuthFailed { status: u16, body: String },
+    #[error("Model API rejected the request (status {status}): {body}")]
+    BadRequest { status: u16, body: String },
+    #[error("Model API failed to serve the request (status {status}): {body}")]
+    ServerError { status: u16, body: String },
+    #[error("Model API returned an unexpected status {status}: {body}")]
+    Unexpected { status: u16, body: String },
+}
+
+impl ModelApiError {
+    pub(crate) fn from_response(status: StatusCode, headers: &HeaderMap, body: &[u8]) -> Self {
+        let body = String::from_utf8_lossy(body).into_owned();
+        let code = status.as_u16();
+
+        match status {
+            StatusCode::TOO_MANY_REQUESTS => Self::RateLimited {
+                status: code,
+                body,
+                retry_after: headers
+                    .get(RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.trim().parse().ok()),
+            },
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Self::AuthFailed { status: code, body },
+            status if status.is_client_error() => Self::BadRequest { status: code, body },
+            status if status.is_server_error() => Self::ServerError { status: code, body },
+            _ => Self::Unexpected { status: code, body },
+        }
+    }
+
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::RateLimited { status, .. }
+            | Self::AuthFailed { status, .. }
+            | Self::BadRequest { status, .. }
+            | Self::ServerError { status, .. }
+            | Self::Unexpected { status, .. } => *status,
+        }
+    }
+
+    pub fn body(&self) -> &str {
+        match self {
+            Self::RateLimited { body, .. }
+            | Self::AuthFailed { body, .. }
+            | Self::BadRequest { body, .. }
+            | Self::ServerError { body, .. }
+            | Self::Unexpected { body, .. } => body,
+        }
+    }
+}
+
+/// A [`ModelApiError`] together with the proof that the model API returned it
+#[derive(Debug, Error)]
+#[error("The error response of the model API was notarised")]
+pub struct NotarisedApiError {
+    #[source]
+    pub error: ModelApiError,
+    pub proof: Proof,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    #[test]
+    fn test_error_classification() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("20"));
+
+        let error = ModelApiError::from_response(StatusCode::TOO_MANY_REQUESTS, &headers, b"slow down");
+        assert!(matches!(
+            error,
+            ModelApiError::RateLimited { status: 429, retry_after: Some(20), .. }
+        ));
+        assert_eq!(error.body(), "slow down");
+
+        let headers = HeaderMap::new();
+        assert!(matches!(
+            ModelApiError::from_response(StatusCode::UNAUTHORIZED, &headers, b""),
+            ModelApiError::AuthFailed { .. }
+        ));
+        assert!(matches!(
+            ModelApiError::from_response(StatusCode::UNPROCESSABLE_ENTITY, &headers, b""),
+            ModelApiError::BadRequest { .. }
+        ));
+        assert!(matches!(
+            ModelApiError::from_response(StatusCode::BAD_GATEWAY, &headers, b""),
+            ModelApiError::ServerError { status: 502, .. }
+        ));
+    }
+}
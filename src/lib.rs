@@ -1,101 +1,594 @@
+mod body_disclosure;
+mod chat_params;
+mod conversation;
 mod model_interactions;
+mod notary_service;
 mod setup_notary;
 mod config;
+mod errors;
+#[cfg(test)]
+mod mock_server;
+mod proof_verification;
+mod py_conversion;
+mod py_errors;
+mod redaction;
+mod session;
+mod streaming;
 mod tlsn_operations;
 
-use crate::config::{Config, ModelSettings};
-use crate::model_interactions::single_interaction_round;
-use crate::setup_notary::setup_connections;
-use crate::tlsn_operations::{build_proof, notarise_session};
+use crate::py_conversion::py_conversation;
+use crate::py_errors::{to_py_err, InvalidInputError, TlsnLangchainError};
+use crate::session::validate_request;
 use anyhow::{Context, Result};
-use pyo3::exceptions::PyTypeError;
+use pyo3::exceptions::PyStopAsyncIteration;
 use pyo3::prelude::PyModule;
-use pyo3::{pyfunction, pymodule, wrap_pyfunction, PyAny, PyErr, PyResult, Python};
+use pyo3::types::PyList;
+use pyo3::{pyclass, pyfunction, pymethods, pymodule, wrap_pyfunction, IntoPy, PyAny, PyObject, PyRef, PyResult, Python};
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
 use tracing::debug;
 
+pub use crate::body_disclosure::{BodyDisclosure, JsonPath};
+pub use crate::chat_params::ChatRequestParams;
+pub use crate::conversation::{validate_conversation, ChatMessage, ContentPart, FunctionCall, FunctionDefinition, ImageUrl, InputAudio, MessageContent, ToolCall, ToolDefinition, ToolType};
+pub use crate::config::{Config, DisclosurePolicy, ModelApiSettings, ModelSettings, NotaryKey, NotaryProtocol, NotarySettings, PrivacySettings, RedactionPolicy, DEFAULT_MAX_RECV_DATA, DEFAULT_MAX_SENT_DATA, DEFAULT_SERVER_DOMAIN};
+pub use crate::errors::{ErrorStage, ModelApiError, NotarisedApiError, VerificationFailure};
+pub use crate::model_interactions::{CompletionMetadata, ModelList, ModelResponse};
+pub use crate::notary_service::{request_session, serve_session, ServiceLimits, SessionRequest, SessionResponse, DEFAULT_HANDSHAKE_TIMEOUT, DEFAULT_SESSION_TIMEOUT};
+pub use crate::proof_verification::{verify_proof, verify_proof_with_root_ca, Proof, VerifiedProof, REDACTED_BYTE};
+pub use crate::redaction::{HeaderPattern, HeaderRule, RedactionScope};
+pub use crate::session::NotarisedSession;
+pub use crate::setup_notary::load_notary_key;
+pub use crate::tlsn_operations::find_ranges;
+
 #[pymodule]
-fn tlsn_langchain(_: Python, m: &PyModule) -> PyResult<()> {
+fn tlsn_langchain(py: Python, m: &PyModule) -> PyResult<()> {
+    py_errors::register(py, m)?;
     m.add_function(wrap_pyfunction!(exec, m)?)?;
+    m.add_function(wrap_pyfunction!(exec_sync, m)?)?;
+    m.add_function(wrap_pyfunction!(py_verify_proof, m)?)?;
+    m.add_function(wrap_pyfunction!(list_models, m)?)?;
+    m.add_class::<ChatRequestParams>()?;
+    m.add_class::<DisclosurePolicy>()?;
+    m.add_class::<ModelApiSettings>()?;
+    m.add_class::<NotarisedClient>()?;
+    m.add_class::<NotarisedModelList>()?;
+    m.add_class::<NotarisedResponse>()?;
+    m.add_class::<NotarisedStream>()?;
+    m.add_class::<NotarySettings>()?;
+    m.add_class::<PyNotarisedSession>()?;
+    m.add_class::<Proof>()?;
+    m.add_class::<RedactionPolicy>()?;
+    m.add_class::<VerifiedProof>()?;
     Ok(())
 }
 
-#[allow(unused_variables)]
+/// Runs a notarised model request.
+///
+/// Resolves to a [`NotarisedResponse`], or to a [`NotarisedStream`] when `stream` is set.
+///
+/// `params` holds the sampling and output parameters, unset ones are left to the API defaults.
+/// `system_prompt` is sent as a `system` message ahead of `messages`.
+/// `api_settings` selects the model API endpoint, and defaults to `api.red-pill.ai`.
+/// `disclosure` selects which parts of the request and response bodies the proof reveals.
+/// `redaction` selects the redacted headers and the secrets, the API key is always redacted.
+/// With `prove_errors`, an error response of the model API is notarised as well.
+/// `notary_settings` selects the notary, and defaults to an in-process dummy notary.
+/// With `validate_model`, the model list of the API is requested first, over the same notarised
+/// connection, and an unknown model is rejected before the inference request is sent.
 #[pyfunction]
-pub fn exec(py: Python, model: String, api_key: String, messages: Vec<String>, tools: Vec<String>, top_p: f64, temperature: f64, stream: bool) -> PyResult<&PyAny> {
+#[pyo3(signature = (model, api_key, messages, tools, params = None, stream = false, system_prompt = None, api_settings = None, disclosure = None, prove_errors = false, notary_settings = None, redaction = None, validate_model = false))]
+#[allow(clippy::too_many_arguments)]
+pub fn exec<'p>(py: Python<'p>, model: String, api_key: String, messages: Vec<&PyAny>, tools: Vec<&PyAny>, params: Option<ChatRequestParams>, stream: bool, system_prompt: Option<String>, api_settings: Option<ModelApiSettings>, disclosure: Option<DisclosurePolicy>, prove_errors: bool, notary_settings: Option<NotarySettings>, redaction: Option<RedactionPolicy>, validate_model: bool) -> PyResult<&'p PyAny> {
+    let mut config = py_config(model, api_key, api_settings, system_prompt, disclosure, notary_settings, redaction)?;
+    config.notary_settings.prove_error_responses = prove_errors;
+    config.model_settings.validate_model = validate_model;
+    let (messages, tools) = py_messages_and_tools(&messages, &tools)?;
+    let params = params.unwrap_or_default();
     pyo3_asyncio::tokio::future_into_py(py, async move {
-        notarised_model_request(model, api_key, messages, tools, top_p, temperature).await.map_err(|e| {
-            PyErr::new::<PyTypeError, _>(e.to_string())
-        })
+        if stream {
+            let (delta_sender, delta_receiver) = mpsc::unbounded_channel();
+            let result = tokio::spawn(notarised_model_stream(config, messages, tools, params, delta_sender));
+
+            let stream = NotarisedStream {
+                deltas: Arc::new(Mutex::new(delta_receiver)),
+                result: Arc::new(Mutex::new(Some(result))),
+            };
+            return Python::with_gil(|py| Ok(stream.into_py(py)));
+        }
+
+        let (response, proof) = notarised_model_request(config, messages, tools, params).await.map_err(to_py_err)?;
+        Python::with_gil(|py| Ok(NotarisedResponse { response, proof: Some(proof) }.into_py(py)))
     })
 }
 
-pub async fn notarised_model_request(model: String, api_key: String, messages: Vec<String>, tools: Vec<String>, top_p: f64, temperature: f64) -> Result<(String, String)> {
-    let config = Config {
-        model_settings: ModelSettings {
-            id: model,
-            api_settings: config::ModelApiSettings::new(api_key),
-            setup_prompt: "Model Prompt: YOU ARE GOING TO BE ACTING AS A HELPFUL ASSISTANT",
-        },
-        privacy_settings: config::PrivacySettings::default(),
-        notary_settings: config::NotarySettings::default(),
-    };
+/// Runs a notarised model request and blocks until the response is notarised.
+///
+/// Takes the arguments of `exec` except `stream`, for synchronous code such as scripts or Jupyter
+/// cells whose event loop is already running. Other Python threads keep running in the meantime.
+#[pyfunction]
+#[pyo3(signature = (model, api_key, messages, tools, params = None, system_prompt = None, api_settings = None, disclosure = None, prove_errors = false, notary_settings = None, redaction = None, validate_model = false))]
+#[allow(clippy::too_many_arguments)]
+pub fn exec_sync(py: Python, model: String, api_key: String, messages: Vec<&PyAny>, tools: Vec<&PyAny>, params: Option<ChatRequestParams>, system_prompt: Option<String>, api_settings: Option<ModelApiSettings>, disclosure: Option<DisclosurePolicy>, prove_errors: bool, notary_settings: Option<NotarySettings>, redaction: Option<RedactionPolicy>, validate_model: bool) -> PyResult<NotarisedResponse> {
+    let mut config = py_config(model, api_key, api_settings, system_prompt, disclosure, notary_settings, redaction)?;
+    config.notary_settings.prove_error_responses = prove_errors;
+    config.model_settings.validate_model = validate_model;
+    let (messages, tools) = py_messages_and_tools(&messages, &tools)?;
+    let params = params.unwrap_or_default();
+
+    let (response, proof) = block_on(py, notarised_model_request(config, messages, tools, params))?;
+    Ok(NotarisedResponse { response, proof: Some(proof) })
+}
+
+/// Runs `future` to completion on the runtime of the async API, with the GIL released
+fn block_on<T: Send>(py: Python, future: impl Future<Output = Result<T>> + Send) -> PyResult<T> {
+    py.allow_threads(|| pyo3_asyncio::tokio::get_runtime().block_on(future))
+        .map_err(to_py_err)
+}
+
+/// Lists the models offered by the model API with a notarised GET on its model list route.
+///
+/// Resolves to a [`NotarisedModelList`], whose proof attests which models the API listed and when.
+#[pyfunction]
+#[pyo3(signature = (api_key, api_settings = None, prove_errors = false, notary_settings = None, redaction = None))]
+pub fn list_models(py: Python, api_key: String, api_settings: Option<ModelApiSettings>, prove_errors: bool, notary_settings: Option<NotarySettings>, redaction: Option<RedactionPolicy>) -> PyResult<&PyAny> {
+    let mut config = py_config(String::new(), api_key, api_settings, None, None, notary_settings, redaction)?;
+    config.notary_settings.prove_error_responses = prove_errors;
+    pyo3_asyncio::tokio::future_into_py(py, async move {
+        let (list, proof) = notarised_model_list(config).await.map_err(to_py_err)?;
+        Ok(NotarisedModelList { list, proof: Some(proof) })
+    })
+}
+
+/// Verifies a proof returned by `exec` against the notary public key (PEM encoded).
+///
+/// The session must have been held with `expected_server_name`, which defaults to the model API domain.
+/// `root_ca_pem` adds trusted roots for the server certificate, e.g. of a gateway with a private PKI.
+#[pyfunction]
+#[pyo3(name = "verify_proof", signature = (proof_json, notary_pubkey_pem, expected_server_name = config::DEFAULT_SERVER_DOMAIN, root_ca_pem = None))]
+pub fn py_verify_proof(proof_json: &str, notary_pubkey_pem: &str, expected_server_name: &str, root_ca_pem: Option<&str>) -> PyResult<VerifiedProof> {
+    verify_proof_with_root_ca(proof_json, notary_pubkey_pem, expected_server_name, root_ca_pem)
+        .context(ErrorStage::ProofVerification)
+        .map_err(to_py_err)
+}
+
+/// Reply of the model API, together with the proof of the notarised session.
+///
+/// `proof` is `None` for the rounds of a `NotarisedSession`, whose proof is returned by `finalize`.
+#[pyclass(frozen)]
+pub struct NotarisedResponse {
+    response: ModelResponse,
+    proof: Option<Proof>,
+}
+
+#[pymethods]
+impl NotarisedResponse {
+    /// The assistant's message, as an OpenAI `choices[0].message` dict
+    #[getter]
+    fn message(&self, py: Python) -> PyResult<PyObject> {
+        json_to_py(py, &self.response.message)
+    }
+
+    /// Text content of the assistant's message
+    #[getter]
+    fn content(&self) -> Option<&str> {
+        self.response.message["content"].as_str()
+    }
+
+    /// Tool calls requested by the assistant, in the OpenAI format
+    #[getter]
+    fn tool_calls(&self, py: Python) -> PyResult<PyObject> {
+        match self.response.message.get("tool_calls") {
+            Some(tool_calls) => json_to_py(py, tool_calls),
+            None => Ok(PyList::empty(py).into()),
+        }
+    }
+
+    /// Token usage reported by the model API, as an OpenAI `usage` dict
+    #[getter]
+    fn usage(&self, py: Python) -> PyResult<Option<PyObject>> {
+        self.response.usage.as_ref().map(|usage| json_to_py(py, usage)).transpose()
+    }
+
+    /// Every choice of the completion, as OpenAI `choices` entries
+    #[getter]
+    fn choices(&self, py: Python) -> PyResult<PyObject> {
+        json_to_py(py, &serde_json::Value::Array(self.response.choices.clone()))
+    }
+
+    /// Completion id given by the model API
+    #[getter]
+    fn id(&self) -> Option<&str> {
+        self.response.metadata.id.as_deref()
+    }
+
+    /// Creation time of the completion (Unix timestamp)
+    #[getter]
+    fn created(&self) -> Option<u64> {
+        self.response.metadata.created
+    }
+
+    /// The model that served the request
+    #[getter]
+    fn model(&self) -> Option<&str> {
+        self.response.metadata.model.as_deref()
+    }
+
+    #[getter]
+    fn system_fingerprint(&self) -> Option<&str> {
+        self.response.metadata.system_fingerprint.as_deref()
+    }
+
+    /// Why the completion ended (`stop`, `length`, `tool_calls`, ...)
+    #[getter]
+    fn finish_reason(&self) -> Option<&str> {
+        self.response.metadata.finish_reason.as_deref()
+    }
+
+    #[getter]
+    fn proof(&self) -> Option<Proof> {
+        self.proof.clone()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("NotarisedResponse(message={})", self.response.message)
+    }
+}
+
+/// Model list of the model API, together with the proof of the notarised session.
+///
+/// `proof` is `None` for the rounds of a `NotarisedSession`, whose proof is returned by `finalize`.
+#[pyclass(frozen)]
+pub struct NotarisedModelList {
+    list: ModelList,
+    proof: Option<Proof>,
+}
+
+#[pymethods]
+impl NotarisedModelList {
+    /// The listed models, as returned by the API
+    #[getter]
+    fn models(&self, py: Python) -> PyResult<PyObject> {
+        json_to_py(py, &serde_json::Value::Array(self.list.models.clone()))
+    }
+
+    /// Ids of the listed models
+    #[getter]
+    fn ids(&self) -> Vec<&str> {
+        self.list.ids()
+    }
+
+    #[getter]
+    fn proof(&self) -> Option<Proof> {
+        self.proof.clone()
+    }
+
+    fn __contains__(&self, model_id: &str) -> bool {
+        self.list.contains(model_id)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("NotarisedModelList(ids={:?})", self.list.ids())
+    }
+}
+
+fn json_to_py(py: Python, value: &serde_json::Value) -> PyResult<PyObject> {
+    let json = py.import("json")?;
+    Ok(json.call_method1("loads", (value.to_string(),))?.into())
+}
+
+/// Async iterator over the deltas of a streamed model response.
+///
+/// Each item is a JSON-encoded `choices[0].delta` object. Once the stream is exhausted,
+/// `result()` resolves to a [`NotarisedResponse`] with the reassembled message and the proof over the whole transcript.
+#[pyclass]
+pub struct NotarisedStream {
+    deltas: Arc<Mutex<UnboundedReceiver<String>>>,
+    result: Arc<Mutex<Option<JoinHandle<Result<(ModelResponse, Proof)>>>>>,
+}
+
+#[pymethods]
+impl NotarisedStream {
+    fn __aiter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __anext__(&self, py: Python) -> PyResult<Option<PyObject>> {
+        let deltas = self.deltas.clone();
+        let next = pyo3_asyncio::tokio::future_into_py(py, async move {
+            match deltas.lock().await.recv().await {
+                Some(delta) => Ok(delta),
+                None => Err(PyStopAsyncIteration::new_err(())),
+            }
+        })?;
+        Ok(Some(next.into()))
+    }
+
+    fn result<'p>(&self, py: Python<'p>) -> PyResult<&'p PyAny> {
+        let result = self.result.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let task = result
+                .lock()
+                .await
+                .take()
+                .ok_or_else(|| TlsnLangchainError::new_err("The stream result was already taken"))?;
+
+            let (response, proof) = task
+                .await
+                .context("Error waiting for the notarisation task")
+                .and_then(|result| result)
+                .map_err(to_py_err)?;
+
+            Ok(NotarisedResponse { response, proof: Some(proof) })
+        })
+    }
+}
+
+/// Client holding the configuration of its requests, each of them notarised in its own session.
+///
+/// `invoke` blocks until the response is notarised, `ainvoke` resolves to it.
+#[pyclass]
+pub struct NotarisedClient {
+    config: Config,
+}
+
+#[pymethods]
+impl NotarisedClient {
+    #[new]
+    #[pyo3(signature = (model, api_key, system_prompt = None, api_settings = None, disclosure = None, prove_errors = false, notary_settings = None, redaction = None, validate_model = false))]
+    #[allow(clippy::too_many_arguments)]
+    fn py_new(model: String, api_key: String, system_prompt: Option<String>, api_settings: Option<ModelApiSettings>, disclosure: Option<DisclosurePolicy>, prove_errors: bool, notary_settings: Option<NotarySettings>, redaction: Option<RedactionPolicy>, validate_model: bool) -> PyResult<Self> {
+        let mut config = py_config(model, api_key, api_settings, system_prompt, disclosure, notary_settings, redaction)?;
+        config.notary_settings.prove_error_responses = prove_errors;
+        config.model_settings.validate_model = validate_model;
+        Ok(Self { config })
+    }
+
+    #[pyo3(signature = (messages, tools = Vec::new(), params = None))]
+    fn invoke(&self, py: Python, messages: Vec<&PyAny>, tools: Vec<&PyAny>, params: Option<ChatRequestParams>) -> PyResult<NotarisedResponse> {
+        let (messages, tools) = py_messages_and_tools(&messages, &tools)?;
+        let request = notarised_model_request(self.config.clone(), messages, tools, params.unwrap_or_default());
+        let (response, proof) = block_on(py, request)?;
+        Ok(NotarisedResponse { response, proof: Some(proof) })
+    }
+
+    #[pyo3(signature = (messages, tools = Vec::new(), params = None))]
+    fn ainvoke<'p>(&self, py: Python<'p>, messages: Vec<&PyAny>, tools: Vec<&PyAny>, params: Option<ChatRequestParams>) -> PyResult<&'p PyAny> {
+        let (messages, tools) = py_messages_and_tools(&messages, &tools)?;
+        let request = notarised_model_request(self.config.clone(), messages, tools, params.unwrap_or_default());
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let (response, proof) = request.await.map_err(to_py_err)?;
+            Ok(NotarisedResponse { response, proof: Some(proof) })
+        })
+    }
+
+    /// Blocking counterpart of the `list_models` function
+    fn list_models(&self, py: Python) -> PyResult<NotarisedModelList> {
+        let (list, proof) = block_on(py, notarised_model_list(self.config.clone()))?;
+        Ok(NotarisedModelList { list, proof: Some(proof) })
+    }
+}
+
+/// Python handle on a [`NotarisedSession`], for multi-round conversations proven by a single proof.
+///
+/// Created with `await NotarisedSession.start(model, api_key)`, every `send` is one round
+/// over the same connection and `finalize` returns the proof covering all of them.
+#[pyclass(name = "NotarisedSession")]
+pub struct PyNotarisedSession {
+    session: Arc<Mutex<Option<NotarisedSession>>>,
+}
+
+#[pymethods]
+impl PyNotarisedSession {
+    #[staticmethod]
+    #[pyo3(signature = (model, api_key, system_prompt = None, api_settings = None, disclosure = None, notary_settings = None, redaction = None))]
+    #[allow(clippy::too_many_arguments)]
+    fn start(py: Python, model: String, api_key: String, system_prompt: Option<String>, api_settings: Option<ModelApiSettings>, disclosure: Option<DisclosurePolicy>, notary_settings: Option<NotarySettings>, redaction: Option<RedactionPolicy>) -> PyResult<&PyAny> {
+        let config = py_config(model, api_key, api_settings, system_prompt, disclosure, notary_settings, redaction)?;
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let session = NotarisedSession::new(config).await.map_err(to_py_err)?;
+
+            Ok(PyNotarisedSession {
+                session: Arc::new(Mutex::new(Some(session))),
+            })
+        })
+    }
+
+    #[pyo3(signature = (messages, tools, params = None))]
+    fn send<'p>(&self, py: Python<'p>, messages: Vec<&PyAny>, tools: Vec<&PyAny>, params: Option<ChatRequestParams>) -> PyResult<&'p PyAny> {
+        let (messages, tools) = py_messages_and_tools(&messages, &tools)?;
+        let params = params.unwrap_or_default();
+        let session = self.session.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let mut session = session.lock().await;
+            let session = session
+                .as_mut()
+                .ok_or_else(|| TlsnLangchainError::new_err("The session was already finalized"))?;
+
+            session
+                .send(messages, tools, &params, None)
+                .await
+                .map(|response| NotarisedResponse { response, proof: None })
+                .map_err(to_py_err)
+        })
+    }
+
+    fn list_models<'p>(&self, py: Python<'p>) -> PyResult<&'p PyAny> {
+        let session = self.session.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let mut session = session.lock().await;
+            let session = session
+                .as_mut()
+                .ok_or_else(|| TlsnLangchainError::new_err("The session was already finalized"))?;
+
+            session
+                .list_models()
+                .await
+                .map(|list| NotarisedModelList { list, proof: None })
+                .map_err(to_py_err)
+        })
+    }
+
+    fn finalize<'p>(&self, py: Python<'p>) -> PyResult<&'p PyAny> {
+        let session = self.session.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let session = session
+                .lock()
+                .await
+                .take()
+                .ok_or_else(|| TlsnLangchainError::new_err("The session was already finalized"))?;
+
+            session.finalize().await.map_err(to_py_err)
+        })
+    }
+}
 
-    debug!("The system is being setup...");
+pub async fn notarised_model_request(config: Config, messages: Vec<ChatMessage>, tools: Vec<ToolDefinition>, params: ChatRequestParams) -> Result<(ModelResponse, Proof)> {
+    run_notarised_request(config, messages, tools, params, None).await
+}
+
+/// Same as [`notarised_model_request`], but requests a streamed response and forwards
+/// every delta to `delta_sender` as it arrives. The proof still covers the full transcript.
+pub async fn notarised_model_stream(config: Config, messages: Vec<ChatMessage>, tools: Vec<ToolDefinition>, params: ChatRequestParams, delta_sender: UnboundedSender<String>) -> Result<(ModelResponse, Proof)> {
+    run_notarised_request(config, messages, tools, params, Some(delta_sender)).await
+}
 
-    // TODO - explore how to do it stateful to avoid redoing the attestation every time
-    let (_, prover_task, mut request_sender) = setup_connections(&config)
-        .await
-        .context("Error setting up connections")?;
+/// Lists the models offered by the model API, with the proof of the notarised GET
+pub async fn notarised_model_list(config: Config) -> Result<(ModelList, Proof)> {
+    let mut session = NotarisedSession::connect(config, false).await?;
 
+    let list = session.list_models().await;
+    finish_session(session, list).await
+}
+
+async fn run_notarised_request(config: Config, messages: Vec<ChatMessage>, tools: Vec<ToolDefinition>, params: ChatRequestParams, delta_sender: Option<UnboundedSender<String>>) -> Result<(ModelResponse, Proof)> {
     debug!("Initialising the message conversation...");
-    let parsed_messages = messages
-        .iter()
-        .map(|m| serde_json::from_str(m))
-        .collect::<Result<Vec<serde_json::Value>, _>>()
-        .context("Error parsing messages")?;
-
-    let parsed_tools = tools
-        .iter()
-        .map(|m| serde_json::from_str(m))
-        .collect::<Result<Vec<serde_json::Value>, _>>()
-        .context("Error parsing tools")?;
-
-    let mut recv_private_data = vec![];
-    let mut sent_private_data = vec![];
-
-    let response = single_interaction_round(
-        &mut request_sender,
-        &config,
-        parsed_messages,
-        parsed_tools,
-        top_p,
-        temperature,
-        &mut recv_private_data,
-        &mut sent_private_data,
-    )
-        .await?;
-
-    debug!("Shutting down the connection with the API...");
-
-    // Notarize the session
-    debug!("Notarizing the session...");
-    let notarised_session = notarise_session(prover_task, &recv_private_data, &sent_private_data)
-        .await
-        .context("Error notarizing the session")?;
-
-    // Build the proof
-    debug!("Building the proof...");
-    let proof = build_proof(notarised_session);
-
-    Ok((response, serde_json::to_string_pretty(&proof)?))
+    validate_request(&messages, &tools, &params, config.model_settings.api_settings.provider)?;
+
+    // A single round, so the server may close the connection right after the response,
+    // unless the model list is requested first
+    let validate_model = config.model_settings.validate_model;
+    let mut session = NotarisedSession::connect(config, validate_model).await?;
+
+    if validate_model {
+        if let Err(e) = check_model(&mut session).await {
+            session.abort();
+            return Err(e);
+        }
+    }
+
+    let response = session
+        .send(messages, tools, &params, delta_sender.as_ref())
+        .await;
+
+    // Close the delta stream before the (slow) notarisation starts
+    drop(delta_sender);
+
+    finish_session(session, response).await
+}
+
+/// Rejects a model the API does not list, before anything is spent on inference and notarisation
+async fn check_model(session: &mut NotarisedSession) -> Result<()> {
+    let list = session.list_models().await?;
+
+    let model_settings = &session.config().model_settings;
+    if !list.contains(&model_settings.id) {
+        return Err(anyhow::anyhow!(
+            "The model `{}` is not offered by {}, available models: {}",
+            model_settings.id,
+            model_settings.api_settings.server_domain,
+            list.ids().join(", ")
+        ))
+        .context(ErrorStage::ModelRequest);
+    }
+
+    Ok(())
+}
+
+/// Notarises the session after its last round, or aborts it when the round failed.
+///
+/// An error response of the model API is notarised too when the notary settings ask for it.
+async fn finish_session<T>(session: NotarisedSession, response: Result<T>) -> Result<(T, Proof)> {
+    let response = match response {
+        Ok(response) => response,
+        Err(e) => {
+            let api_error = e.downcast_ref::<ModelApiError>().cloned();
+            return match api_error {
+                Some(error) if session.config().notary_settings.prove_error_responses => {
+                    debug!("Notarising the error response...");
+                    let proof = session.finalize().await?;
+                    Err(NotarisedApiError { error, proof }.into())
+                }
+                _ => {
+                    session.abort();
+                    Err(e)
+                }
+            };
+        }
+    };
+
+    let proof = session.finalize().await?;
+
+    Ok((response, proof))
+}
+
+/// Configuration with the default privacy and notary settings
+pub fn default_config(model: String, api_settings: ModelApiSettings) -> Config {
+    Config {
+        model_settings: ModelSettings::new(model, api_settings),
+        privacy_settings: PrivacySettings::default(),
+        notary_settings: NotarySettings::default(),
+    }
+}
+
+/// Builds the configuration from the arguments passed from Python
+fn py_config(model: String, api_key: String, api_settings: Option<ModelApiSettings>, system_prompt: Option<String>, disclosure: Option<DisclosurePolicy>, notary_settings: Option<NotarySettings>, redaction: Option<RedactionPolicy>) -> PyResult<Config> {
+    // Completes the endpoint passed from Python (or the default one) with the API key
+    let api_settings = match api_settings {
+        Some(api_settings) => ModelApiSettings { api_key, ..api_settings },
+        None => ModelApiSettings::new(api_key),
+    };
+
+    let mut config = default_config(model, api_settings);
+    if disclosure.as_ref().is_some_and(|disclosure| disclosure.system_prompt_only) && system_prompt.is_none() {
+        return Err(InvalidInputError::new_err("`reveal_system_prompt_only` needs a `system_prompt`"));
+    }
+    config.model_settings.setup_prompt = system_prompt;
+    if let Some(disclosure) = disclosure {
+        let request = disclosure.request_disclosure(config.model_settings.api_settings.provider);
+        config.privacy_settings = PrivacySettings::with_body_disclosure(request, disclosure.response);
+    }
+    if let Some(redaction) = redaction {
+        config.privacy_settings.request_header_rules = redaction.request_header_rules;
+        config.privacy_settings.response_header_rules = redaction.response_header_rules;
+        config.privacy_settings.secrets = redaction.secrets;
+    }
+    if let Some(notary_settings) = notary_settings {
+        config.notary_settings = notary_settings;
+    }
+    Ok(config)
+}
+
+/// Converts the messages and tools passed from Python, before anything is set up
+fn py_messages_and_tools(messages: &[&PyAny], tools: &[&PyAny]) -> PyResult<(Vec<ChatMessage>, Vec<ToolDefinition>)> {
+    py_conversation(messages, tools)
+        .context(ErrorStage::InvalidInput)
+        .map_err(to_py_err)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock_server::{MockModelServer, MOCK_API_KEY, MOCK_CONTENT, MOCK_MALFORMED_MODEL, MOCK_SERVER_DOMAIN};
+    use crate::setup_notary::notary_public_key;
+    use p256::pkcs8::{EncodePublicKey, LineEnding};
     use std::env;
 
     #[tokio::test]
+    #[ignore = "needs REDPILL_API_KEY and network access"]
     async fn test_generate_conversation_attribution() -> Result<()> {
         dotenv::dotenv().ok();
         let api_key = env::var("REDPILL_API_KEY")?;
@@ -137,13 +630,103 @@ mod tests {
             }"
         ].iter().map(|s| s.to_string()).collect::<Vec<String>>();
 
-        let top_p = 0.85;
-        let temperature = 0.3;
+        let messages = messages.iter().map(|m| serde_json::from_str(m)).collect::<Result<Vec<_>, _>>()?;
+        let tools = tools.iter().map(|t| serde_json::from_str(t)).collect::<Result<Vec<_>, _>>()?;
+        let params = ChatRequestParams {
+            top_p: Some(0.85),
+            temperature: Some(0.3),
+            ..ChatRequestParams::default()
+        };
+
+        let config = default_config(model, ModelApiSettings::new(api_key));
+        let (response, proof) = notarised_model_request(config, messages, tools, params).await?;
+        println!("Response: {}", response.message);
+        println!("Proof: {}", serde_json::to_string(proof.tls_proof())?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_offline_notarisation_is_verified() -> Result<()> {
+        let server = MockModelServer::start().await?;
+        let config = default_config("mock-gpt".to_string(), server.api_settings());
+        let notary_pubkey_pem = notary_public_key(&config.notary_settings)?.to_public_key_pem(LineEnding::LF)?;
+
+        let messages = vec![ChatMessage::user("hi")];
+        let (response, proof) =
+            notarised_model_request(config, messages, vec![], ChatRequestParams::default()).await?;
+        assert_eq!(response.message["content"], MOCK_CONTENT);
+        assert_eq!(response.metadata.finish_reason.as_deref(), Some("stop"));
+
+        let proof_json = proof.to_json()?;
+        let verified =
+            verify_proof_with_root_ca(&proof_json, &notary_pubkey_pem, MOCK_SERVER_DOMAIN, Some(&server.root_ca_pem))?;
+        assert_eq!(verified.server_name, MOCK_SERVER_DOMAIN);
+        assert!(verified.recv.contains(MOCK_CONTENT));
+        assert!(!verified.sent.contains(MOCK_API_KEY));
+
+        // The mock CA is not a web root
+        let error = verify_proof(&proof_json, &notary_pubkey_pem, MOCK_SERVER_DOMAIN).unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(VerificationFailure::InvalidSignature)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_offline_stream_and_model_validation() -> Result<()> {
+        let server = MockModelServer::start().await?;
+        let messages = vec![ChatMessage::user("hi")];
+
+        let mut config = default_config("mock-gpt-mini".to_string(), server.api_settings());
+        config.model_settings.validate_model = true;
+        let (delta_sender, mut delta_receiver) = mpsc::unbounded_channel();
+        let (response, _) =
+            notarised_model_stream(config, messages.clone(), vec![], ChatRequestParams::default(), delta_sender).await?;
+        assert_eq!(response.message["content"], MOCK_CONTENT);
+
+        let mut streamed = String::new();
+        while let Some(delta) = delta_receiver.recv().await {
+            let delta: serde_json::Value = serde_json::from_str(&delta)?;
+            streamed += delta["content"].as_str().unwrap_or_default();
+        }
+        assert_eq!(streamed, MOCK_CONTENT);
+
+        let mut config = default_config("gpt-4o".to_string(), server.api_settings());
+        config.model_settings.validate_model = true;
+        let error = notarised_model_request(config, messages, vec![], ChatRequestParams::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(ErrorStage::ModelRequest)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_offline_malformed_response() -> Result<()> {
+        let server = MockModelServer::start().await?;
+        let config = default_config(MOCK_MALFORMED_MODEL.to_string(), server.api_settings());
+
+        let error = notarised_model_request(config, vec![ChatMessage::user("hi")], vec![], ChatRequestParams::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(ErrorStage::ModelRequest)));
+        assert!(error.downcast_ref::<ModelApiError>().is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_session_counts_only_sent_rounds() -> Result<()> {
+        let server = MockModelServer::start().await?;
+        let mut session = NotarisedSession::new(default_config("mock-gpt".to_string(), server.api_settings())).await?;
+        let params = ChatRequestParams::default();
+
+        // Rejected before anything is written to the connection
+        assert!(session.send(vec![], vec![], &params, None).await.is_err());
+        assert_eq!(session.rounds(), 0);
 
-        let (response, proof) = notarised_model_request(model, api_key, messages, tools, top_p, temperature).await?;
-        println!("Response: {}", response);
-        println!("Proof: {}", proof.replace("\n", "").replace(" ", ""));
+        session.list_models().await?;
+        session.send(vec![ChatMessage::user("hi")], vec![], &params, None).await?;
+        assert_eq!(session.rounds(), 2);
 
+        session.abort();
         Ok(())
     }
 
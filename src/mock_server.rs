@@ -0,0 +1,190 @@
+//! Local HTTPS server serving canned OpenAI-compatible responses, so the notarise → prove → verify
+//! pipeline is tested without network access or an API key.
+
+use crate::config::ModelApiSettings;
+use anyhow::{Context, Result};
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+
+/// Name the server certificate is issued for, it does not need to resolve
+pub(crate) const MOCK_SERVER_DOMAIN: &str = "mock-llm.test";
+/// Only key the server accepts
+pub(crate) const MOCK_API_KEY: &str = "sk-mock-0123456789abcdef";
+pub(crate) const MOCK_MODELS: [&str; 2] = ["mock-gpt", "mock-gpt-mini"];
+/// Model whose completions come back with a `200 OK` status but a body that is not JSON
+pub(crate) const MOCK_MALFORMED_MODEL: &str = "mock-malformed";
+/// Content of every assistant message
+pub(crate) const MOCK_CONTENT: &str = "Hello from the mock model!";
+
+pub(crate) struct MockModelServer {
+    pub address: SocketAddr,
+    /// PEM encoded self-signed CA that issued the server certificate
+    pub root_ca_pem: String,
+}
+
+impl MockModelServer {
+    /// Issues a certificate for [`MOCK_SERVER_DOMAIN`] and serves it on a free local port
+    pub async fn start() -> Result<Self> {
+        let mut ca_params = CertificateParams::new(vec![]);
+        ca_params
+            .distinguished_name
+            .push(DnType::CommonName, "tlsn-langchain test CA");
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = rcgen::Certificate::from_params(ca_params)?;
+
+        let server_cert = rcgen::Certificate::from_params(CertificateParams::new(vec![
+            MOCK_SERVER_DOMAIN.to_string(),
+        ]))?;
+        // The prover only speaks TLS 1.2 with ECDHE, which the rustls defaults offer
+        let tls_config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![rustls::Certificate(server_cert.serialize_der_with_signer(&ca)?)],
+                rustls::PrivateKey(server_cert.serialize_private_key_der()),
+            )
+            .context("Error building the mock server TLS configuration")?;
+        let acceptor = TlsAcceptor::from(Arc::new(tls_config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(tls_stream) = acceptor.accept(socket).await else {
+                        return;
+                    };
+                    let _ = hyper::server::conn::http1::Builder::new()
+                        .serve_connection(TokioIo::new(tls_stream), service_fn(respond))
+                        .await;
+                });
+            }
+        });
+
+        Ok(Self {
+            address,
+            root_ca_pem: ca.serialize_pem()?,
+        })
+    }
+
+    /// Endpoint of the mock server, reached by its address and trusting its CA
+    pub fn api_settings(&self) -> ModelApiSettings {
+        ModelApiSettings {
+            server_domain: MOCK_SERVER_DOMAIN.to_string(),
+            port: self.address.port(),
+            server_address: Some(self.address.to_string()),
+            root_ca_pem: Some(self.root_ca_pem.clone()),
+            ..ModelApiSettings::new(MOCK_API_KEY.to_string())
+        }
+    }
+}
+
+async fn respond(request: Request<Incoming>) -> Result<Response<Full<Bytes>>, hyper::Error> {
+    let (parts, body) = request.into_parts();
+    let body = body.collect().await?.to_bytes();
+
+    let authorized = parts
+        .headers
+        .get(AUTHORIZATION)
+        .is_some_and(|value| value.as_bytes() == format!("Bearer {MOCK_API_KEY}").as_bytes());
+    if !authorized {
+        return Ok(json_response(
+            StatusCode::UNAUTHORIZED,
+            json!({ "error": { "message": "Invalid API key", "type": "invalid_request_error" } }),
+        ));
+    }
+
+    let response = match (&parts.method, parts.uri.path()) {
+        (&Method::GET, "/v1/models") => json_response(
+            StatusCode::OK,
+            json!({
+                "object": "list",
+                "data": MOCK_MODELS.map(|id| json!({ "id": id, "object": "model", "owned_by": "mock" })),
+            }),
+        ),
+        (&Method::POST, "/v1/chat/completions") => {
+            let request: Value = serde_json::from_slice(&body).unwrap_or_default();
+            let model = request["model"].as_str().unwrap_or_default();
+            if model == MOCK_MALFORMED_MODEL {
+                Response::new(Full::new(Bytes::from_static(b"{\"choices\": [")))
+            } else if request["stream"] == true {
+                stream_response(model)
+            } else {
+                json_response(StatusCode::OK, completion(model))
+            }
+        }
+        _ => json_response(
+            StatusCode::NOT_FOUND,
+            json!({ "error": { "message": "Unknown route", "type": "invalid_request_error" } }),
+        ),
+    };
+
+    Ok(response)
+}
+
+fn completion(model: &str) -> Value {
+    json!({
+        "id": "chatcmpl-mock",
+        "object": "chat.completion",
+        "created": 1_700_000_000,
+        "model": model,
+        "system_fingerprint": "fp_mock",
+        "choices": [{
+            "index": 0,
+            "message": { "role": "assistant", "content": MOCK_CONTENT },
+            "finish_reason": "stop",
+        }],
+        "usage": { "prompt_tokens": 12, "completion_tokens": 6, "total_tokens": 18 },
+    })
+}
+
+/// The completion as server-sent events, one word per delta
+fn stream_response(model: &str) -> Response<Full<Bytes>> {
+    let chunk = |delta: Value, finish_reason: Value, usage: Value| {
+        let chunk = json!({
+            "id": "chatcmpl-mock",
+            "object": "chat.completion.chunk",
+            "created": 1_700_000_000,
+            "model": model,
+            "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+            "usage": usage,
+        });
+        format!("data: {chunk}\n\n")
+    };
+
+    let mut events = chunk(json!({ "role": "assistant", "content": "" }), Value::Null, Value::Null);
+    for word in MOCK_CONTENT.split_inclusive(' ') {
+        events += &chunk(json!({ "content": word }), Value::Null, Value::Null);
+    }
+    events += &chunk(
+        json!({}),
+        json!("stop"),
+        json!({ "prompt_tokens": 12, "completion_tokens": 6, "total_tokens": 18 }),
+    );
+    events += "data: [DONE]\n\n";
+
+    Response::builder()
+        .header(CONTENT_TYPE, "text/event-stream")
+        .body(Full::new(Bytes::from(events)))
+        .expect("Static response parts are valid")
+}
+
+fn json_response(status: StatusCode, body: Value) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(body.to_string())))
+        .expect("Static response parts are valid")
+}
@@ -1,109 +1,1018 @@
-use crate::config::{Config, ModelSettings};
-use crate::tlsn_operations::extract_private_data;
-use anyhow::{Context, Result};
+use crate::chat_params::ChatRequestParams;
+use crate::config::{ApiProvider, Config, ModelSettings};
+use crate::errors::{ErrorStage, ModelApiError};
+use crate::streaming::{MessageAccumulator, SseParser};
+use anyhow::{bail, Context, Result};
 use http_body_util::BodyExt;
 use hyper::client::conn::http1::SendRequest;
-use hyper::header::{AUTHORIZATION, CONNECTION, CONTENT_TYPE, HOST};
+use hyper::header::{HeaderName, AUTHORIZATION, CONNECTION, CONTENT_TYPE, HOST};
+use hyper::body::{Bytes, Incoming};
 use hyper::{Method, StatusCode};
+use serde_json::{json, Map, Value};
+use tokio::sync::mpsc::UnboundedSender;
 use tracing::debug;
 
+/// Version of the Anthropic Messages API the adapter speaks
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Anthropic requires `max_tokens`, while OpenAI-compatible APIs default to the model's limit
+const ANTHROPIC_DEFAULT_MAX_TOKENS: u64 = 4096;
+
+/// Translates between the OpenAI-shaped conversation used by the LangChain wrapper
+/// and the wire format of a model provider.
+pub(crate) trait ChatProvider: Sync {
+    /// Builds the JSON body of an inference request
+    fn request_body(
+        &self,
+        model_settings: &ModelSettings,
+        messages: Vec<Value>,
+        tools: Vec<Value>,
+        params: &ChatRequestParams,
+        stream: bool,
+    ) -> Result<Value>;
+
+    /// Rejects the parameters the API does not accept, beyond the OpenAI ranges [`ChatRequestParams::validate`]
+    /// checks, so they fail before the MPC setup
+    fn validate_params(&self, params: &ChatRequestParams) -> Result<()>;
+
+    /// Headers authenticating the request with the API key
+    fn auth_headers(&self, api_key: &str) -> Vec<(HeaderName, String)>;
+
+    /// Extracts the assistant's message from a response body, as an OpenAI `choices[0].message`
+    fn assistant_message(&self, response: &Value) -> Result<Value>;
+
+    /// Converts a streamed event into an OpenAI `choices[0].delta`, if it carries one
+    fn stream_delta(&self, event: &Value) -> Option<Value>;
+
+    /// Message of a streamed event that reports an error, e.g. the server overloaded mid-stream
+    fn stream_error(&self, event: &Value) -> Option<String>;
+
+    /// Extracts the token usage of a response body or streamed event, as an OpenAI `usage`
+    fn usage(&self, response: &Value) -> Option<Value>;
+
+    /// Extracts the completion metadata a response body or streamed event carries
+    fn metadata(&self, response: &Value) -> CompletionMetadata;
+
+    /// Extracts every choice of a response body, as OpenAI `choices` entries
+    fn choices(&self, response: &Value) -> Result<Vec<Value>>;
+}
+
+/// Guesses the provider that produced a response body or streamed event from its shape
+pub(crate) fn detect_provider(response: &Value) -> &'static dyn ChatProvider {
+    if response.get("choices").is_some() {
+        &OpenAiProvider
+    } else {
+        &AnthropicProvider
+    }
+}
+
+/// The assistant's reply to one interaction round
+#[derive(Debug, Clone)]
+pub struct ModelResponse {
+    /// The assistant's message, as an OpenAI `choices[0].message`
+    pub message: Value,
+    /// Token usage reported by the model API, as an OpenAI `usage`
+    pub usage: Option<Value>,
+    pub metadata: CompletionMetadata,
+    /// Every choice of the completion, as OpenAI `choices` entries (`index`, `message`, `finish_reason`, ...).
+    /// Only the first one is kept for streamed responses.
+    pub choices: Vec<Value>,
+}
+
+/// Metadata of a completion, as reported by the model API
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompletionMetadata {
+    pub id: Option<String>,
+    /// Unix timestamp, only reported by the OpenAI-compatible APIs
+    pub created: Option<u64>,
+    /// The model that actually served the request
+    pub model: Option<String>,
+    pub system_fingerprint: Option<String>,
+    /// Why the first choice ended, in the OpenAI terms (`stop`, `length`, `tool_calls`, ...)
+    pub finish_reason: Option<String>,
+}
+
+impl CompletionMetadata {
+    /// Completes the metadata with the fields reported by a later chunk of a stream
+    pub(crate) fn merge(&mut self, other: CompletionMetadata) {
+        self.id = other.id.or(self.id.take());
+        self.created = other.created.or(self.created);
+        self.model = other.model.or(self.model.take());
+        self.system_fingerprint = other.system_fingerprint.or(self.system_fingerprint.take());
+        self.finish_reason = other.finish_reason.or(self.finish_reason.take());
+    }
+}
+
+/// Models offered by the API, as listed by its model list route
+#[derive(Debug, Clone)]
+pub struct ModelList {
+    /// The listed models, e.g. `{"id": "gpt-4o", "object": "model", ...}`
+    pub models: Vec<Value>,
+}
+
+impl ModelList {
+    /// Reads the `data` array of the OpenAI and Anthropic model lists, or a bare array of models
+    fn from_response(response: &Value) -> Result<Self> {
+        let models = match response {
+            Value::Array(models) => models,
+            _ => response["data"]
+                .as_array()
+                .context("The response does not contain a model list")?,
+        };
+        Ok(Self {
+            models: models.clone(),
+        })
+    }
+
+    pub fn ids(&self) -> Vec<&str> {
+        self.models
+            .iter()
+            .filter_map(|model| model["id"].as_str())
+            .collect()
+    }
+
+    pub fn contains(&self, model_id: &str) -> bool {
+        self.ids().contains(&model_id)
+    }
+}
+
+pub(crate) fn chat_provider(provider: ApiProvider) -> &'static dyn ChatProvider {
+    match provider {
+        ApiProvider::OpenAi => &OpenAiProvider,
+        ApiProvider::Anthropic => &AnthropicProvider,
+    }
+}
+
+/// OpenAI `/v1/chat/completions` and the compatible APIs (red-pill, OpenRouter, vLLM, ...)
+pub(crate) struct OpenAiProvider;
+
+impl ChatProvider for OpenAiProvider {
+    fn request_body(
+        &self,
+        model_settings: &ModelSettings,
+        messages: Vec<Value>,
+        tools: Vec<Value>,
+        params: &ChatRequestParams,
+        stream: bool,
+    ) -> Result<Value> {
+        let mut json_body = Map::new();
+        json_body.insert("model".to_string(), json!(model_settings.id));
+        json_body.insert("messages".to_string(), serde_json::to_value(messages).context("Error serializing messages")?);
+        json_body.insert("tools".to_string(), serde_json::to_value(tools).context("Error serializing tools")?);
+        params.insert_into(&mut json_body)?;
+        json_body.insert("stream".to_string(), json!(stream));
+        if stream {
+            // Otherwise the usage is missing from streamed responses
+            json_body.insert("stream_options".to_string(), json!({ "include_usage": true }));
+        }
+        Ok(Value::Object(json_body))
+    }
+
+    fn validate_params(&self, _params: &ChatRequestParams) -> Result<()> {
+        Ok(())
+    }
+
+    fn auth_headers(&self, api_key: &str) -> Vec<(HeaderName, String)> {
+        vec![(AUTHORIZATION, format!("Bearer {}", api_key))]
+    }
+
+    fn assistant_message(&self, response: &Value) -> Result<Value> {
+        let message = &response["choices"][0]["message"];
+        if message.is_null() {
+            bail!("The response does not contain an assistant message");
+        }
+        Ok(message.clone())
+    }
+
+    fn stream_delta(&self, event: &Value) -> Option<Value> {
+        let delta = &event["choices"][0]["delta"];
+        (!delta.is_null()).then(|| delta.clone())
+    }
+
+    fn stream_error(&self, event: &Value) -> Option<String> {
+        let error = event.get("error")?;
+        Some(error["message"].as_str().map_or_else(|| error.to_string(), str::to_string))
+    }
+
+    fn usage(&self, response: &Value) -> Option<Value> {
+        let usage = &response["usage"];
+        usage.is_object().then(|| usage.clone())
+    }
+
+    fn metadata(&self, response: &Value) -> CompletionMetadata {
+        CompletionMetadata {
+            id: response["id"].as_str().map(str::to_string),
+            created: response["created"].as_u64(),
+            model: response["model"].as_str().map(str::to_string),
+            system_fingerprint: response["system_fingerprint"].as_str().map(str::to_string),
+            finish_reason: response["choices"][0]["finish_reason"].as_str().map(str::to_string),
+        }
+    }
+
+    fn choices(&self, response: &Value) -> Result<Vec<Value>> {
+        response["choices"]
+            .as_array()
+            .cloned()
+            .context("The response does not contain any choices")
+    }
+}
+
+/// Anthropic Messages API (`/v1/messages`)
+pub(crate) struct AnthropicProvider;
+
+impl ChatProvider for AnthropicProvider {
+    fn request_body(
+        &self,
+        model_settings: &ModelSettings,
+        messages: Vec<Value>,
+        tools: Vec<Value>,
+        params: &ChatRequestParams,
+        stream: bool,
+    ) -> Result<Value> {
+        let (system, messages) = anthropic_messages(messages)?;
+
+        let tools = tools
+            .iter()
+            .map(|tool| {
+                let function = &tool["function"];
+                let name = function["name"].as_str().context("Tool is missing its function name")?;
+                let mut anthropic_tool = Map::new();
+                anthropic_tool.insert("name".to_string(), json!(name));
+                if let Some(description) = function["description"].as_str() {
+                    anthropic_tool.insert("description".to_string(), json!(description));
+                }
+                anthropic_tool.insert(
+                    "input_schema".to_string(),
+                    function.get("parameters").cloned().unwrap_or_else(|| json!({ "type": "object" })),
+                );
+                Ok(Value::Object(anthropic_tool))
+            })
+            .collect::<Result<Vec<Value>>>()?;
+
+        let mut json_body = Map::new();
+        json_body.insert("model".to_string(), json!(model_settings.id));
+        json_body.insert(
+            "max_tokens".to_string(),
+            json!(params.max_tokens.unwrap_or(ANTHROPIC_DEFAULT_MAX_TOKENS)),
+        );
+        if let Some(system) = system {
+            json_body.insert("system".to_string(), json!(system));
+        }
+        json_body.insert("messages".to_string(), json!(messages));
+        if !tools.is_empty() {
+            json_body.insert("tools".to_string(), json!(tools));
+        }
+        insert_anthropic_params(&mut json_body, params)?;
+        json_body.insert("stream".to_string(), json!(stream));
+        Ok(Value::Object(json_body))
+    }
+
+    fn validate_params(&self, params: &ChatRequestParams) -> Result<()> {
+        let unsupported = [
+            ("seed", params.seed.is_some()),
+            ("response_format", params.response_format.is_some()),
+            ("n", params.n.is_some_and(|n| n > 1)),
+            ("presence_penalty", params.presence_penalty.is_some()),
+            ("frequency_penalty", params.frequency_penalty.is_some()),
+            ("logprobs", params.logprobs == Some(true)),
+        ];
+        if let Some((name, _)) = unsupported.iter().find(|(_, set)| *set) {
+            bail!("`{name}` is not supported by the Anthropic API");
+        }
+
+        // Anthropic accepts 0 to 1, where OpenAI accepts 0 to 2
+        if let Some(temperature) = params.temperature.filter(|&temperature| temperature > 1.0) {
+            bail!("`temperature` must be between 0 and 1 for the Anthropic API, got {temperature}");
+        }
+
+        Ok(())
+    }
+
+    fn auth_headers(&self, api_key: &str) -> Vec<(HeaderName, String)> {
+        vec![
+            (HeaderName::from_static("x-api-key"), api_key.to_string()),
+            (HeaderName::from_static("anthropic-version"), ANTHROPIC_VERSION.to_string()),
+        ]
+    }
+
+    fn assistant_message(&self, response: &Value) -> Result<Value> {
+        let blocks = response["content"]
+            .as_array()
+            .context("The response does not contain any content blocks")?;
+
+        let mut text: Option<String> = None;
+        let mut tool_calls = vec![];
+        for block in blocks {
+            match block["type"].as_str() {
+                Some("text") => {
+                    text.get_or_insert_with(String::new)
+                        .push_str(block["text"].as_str().unwrap_or_default());
+                }
+                Some("tool_use") => tool_calls.push(json!({
+                    "id": block["id"],
+                    "type": "function",
+                    "function": {
+                        "name": block["name"],
+                        "arguments": block["input"].to_string(),
+                    },
+                })),
+                _ => {}
+            }
+        }
+
+        let mut message = Map::new();
+        message.insert("role".to_string(), json!("assistant"));
+        message.insert("content".to_string(), json!(text));
+        if !tool_calls.is_empty() {
+            message.insert("tool_calls".to_string(), json!(tool_calls));
+        }
+        Ok(Value::Object(message))
+    }
+
+    fn stream_delta(&self, event: &Value) -> Option<Value> {
+        // Content block indices are used as tool call indices, the accumulator skips the gaps
+        let index = &event["index"];
+        match event["type"].as_str()? {
+            "message_start" => Some(json!({ "role": "assistant" })),
+            "content_block_start" => {
+                let block = &event["content_block"];
+                (block["type"] == "tool_use").then(|| json!({ "tool_calls": [{
+                    "index": index,
+                    "id": block["id"],
+                    "type": "function",
+                    "function": { "name": block["name"], "arguments": "" },
+                }] }))
+            }
+            "content_block_delta" => {
+                let delta = &event["delta"];
+                match delta["type"].as_str()? {
+                    "text_delta" => Some(json!({ "content": delta["text"] })),
+                    "input_json_delta" => Some(json!({ "tool_calls": [{
+                        "index": index,
+                        "function": { "arguments": delta["partial_json"] },
+                    }] })),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn stream_error(&self, event: &Value) -> Option<String> {
+        if event["type"] != "error" {
+            return None;
+        }
+        let error = &event["error"];
+        Some(error["message"].as_str().map_or_else(|| error.to_string(), str::to_string))
+    }
+
+    fn usage(&self, response: &Value) -> Option<Value> {
+        // `message_start` events nest the message, `message_delta` ones carry the output tokens
+        let usage = match response["type"].as_str() {
+            Some("message_start") => &response["message"]["usage"],
+            _ => &response["usage"],
+        };
+        let usage = usage.as_object()?;
+
+        let mut converted = Map::new();
+        if let Some(input_tokens) = usage.get("input_tokens") {
+            converted.insert("prompt_tokens".to_string(), input_tokens.clone());
+        }
+        if let Some(output_tokens) = usage.get("output_tokens") {
+            converted.insert("completion_tokens".to_string(), output_tokens.clone());
+        }
+        Some(Value::Object(converted))
+    }
+
+    fn metadata(&self, response: &Value) -> CompletionMetadata {
+        // `message_start` events nest the message, `message_delta` ones carry the stop reason
+        let message = match response["type"].as_str() {
+            Some("message_start") => &response["message"],
+            _ => response,
+        };
+        let stop_reason = message["stop_reason"]
+            .as_str()
+            .or_else(|| response["delta"]["stop_reason"].as_str());
+
+        CompletionMetadata {
+            id: message["id"].as_str().map(str::to_string),
+            created: None,
+            model: message["model"].as_str().map(str::to_string),
+            system_fingerprint: None,
+            finish_reason: stop_reason.map(|reason| {
+                match reason {
+                    "end_turn" | "stop_sequence" => "stop",
+                    "max_tokens" => "length",
+                    "tool_use" => "tool_calls",
+                    reason => reason,
+                }
+                .to_string()
+            }),
+        }
+    }
+
+    fn choices(&self, response: &Value) -> Result<Vec<Value>> {
+        Ok(vec![json!({
+            "index": 0,
+            "message": self.assistant_message(response)?,
+            "finish_reason": self.metadata(response).finish_reason,
+        })])
+    }
+}
+
+/// Maps the OpenAI request parameters onto their Anthropic equivalents, rejecting those without one
+fn insert_anthropic_params(json_body: &mut Map<String, Value>, params: &ChatRequestParams) -> Result<()> {
+    AnthropicProvider.validate_params(params)?;
+
+    if let Some(temperature) = params.temperature {
+        json_body.insert("temperature".to_string(), json!(temperature));
+    }
+    if let Some(top_p) = params.top_p {
+        json_body.insert("top_p".to_string(), json!(top_p));
+    }
+    if let Some(stop) = &params.stop {
+        json_body.insert("stop_sequences".to_string(), json!(stop));
+    }
+    if let Some(tool_choice) = &params.tool_choice {
+        let tool_choice = match tool_choice.as_str() {
+            Some("auto") => json!({ "type": "auto" }),
+            Some("none") => json!({ "type": "none" }),
+            Some("required") => json!({ "type": "any" }),
+            _ => json!({ "type": "tool", "name": tool_choice["function"]["name"] }),
+        };
+        json_body.insert("tool_choice".to_string(), tool_choice);
+    }
+    if let Some(user) = &params.user {
+        json_body.insert("metadata".to_string(), json!({ "user_id": user }));
+    }
+
+    Ok(())
+}
+
+/// Converts OpenAI-style messages into Anthropic ones.
+///
+/// System messages are lifted into the separate `system` field, tool calls and tool results
+/// become `tool_use` / `tool_result` content blocks, and consecutive messages of the same role
+/// are merged as Anthropic requires user and assistant turns to alternate.
+fn anthropic_messages(messages: Vec<Value>) -> Result<(Option<String>, Vec<Value>)> {
+    let mut system: Vec<String> = vec![];
+    let mut converted: Vec<(String, Vec<Value>)> = vec![];
+
+    for message in messages {
+        let role = message["role"].as_str().context("Message is missing its role")?;
+
+        let (role, blocks) = match role {
+            "system" | "developer" => {
+                system.push(text_content(&message["content"]));
+                continue;
+            }
+            "user" => ("user", content_blocks(&message["content"])),
+            "assistant" => {
+                let mut blocks = content_blocks(&message["content"]);
+                for tool_call in message["tool_calls"].as_array().into_iter().flatten() {
+                    let arguments = tool_call["function"]["arguments"].as_str().unwrap_or("{}");
+                    blocks.push(json!({
+                        "type": "tool_use",
+                        "id": tool_call["id"],
+                        "name": tool_call["function"]["name"],
+                        "input": serde_json::from_str::<Value>(arguments)
+                            .context("Error parsing tool call arguments")?,
+                    }));
+                }
+                ("assistant", blocks)
+            }
+            "tool" => (
+                "user",
+                vec![json!({
+                    "type": "tool_result",
+                    "tool_use_id": message["tool_call_id"],
+                    "content": text_content(&message["content"]),
+                })],
+            ),
+            role => bail!("Unsupported message role `{}`", role),
+        };
+
+        match converted.last_mut() {
+            Some((last_role, last_blocks)) if last_role == role => last_blocks.extend(blocks),
+            _ => converted.push((role.to_string(), blocks)),
+        }
+    }
+
+    let system = (!system.is_empty()).then(|| system.join("\n\n"));
+    let messages = converted
+        .into_iter()
+        .map(|(role, content)| json!({ "role": role, "content": content }))
+        .collect();
+
+    Ok((system, messages))
+}
+
+/// OpenAI content is either a string or a list of parts, Anthropic expects content blocks
+fn content_blocks(content: &Value) -> Vec<Value> {
+    match content {
+        Value::String(text) if text.is_empty() => vec![],
+        Value::String(text) => vec![json!({ "type": "text", "text": text })],
+        Value::Array(parts) => parts
+            .iter()
+            .filter(|part| part["type"] == "text")
+            .map(|part| json!({ "type": "text", "text": part["text"] }))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+fn text_content(content: &Value) -> String {
+    match content {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part["text"].as_str())
+            .collect::<Vec<&str>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// `rounds` is incremented once the request is handed to `request_sender`, from then on the round is
+/// part of the transcript whatever its outcome
+#[allow(clippy::too_many_arguments)]
 pub(super) async fn single_interaction_round(
     request_sender: &mut SendRequest<String>,
+    rounds: &mut usize,
     config: &Config,
     messages: Vec<serde_json::Value>,
     tools: Vec<serde_json::Value>,
-    top_p: f64, temperature: f64,
-    recv_private_data: &mut Vec<Vec<u8>>,
-    sent_private_data: &mut Vec<Vec<u8>>,
-) -> Result<String> {
+    params: &ChatRequestParams,
+    keep_alive: bool,
+    delta_sender: Option<&UnboundedSender<String>>,
+) -> Result<ModelResponse> {
+    let provider = chat_provider(config.model_settings.api_settings.provider);
 
     // Prepare the Request to send to the model's API
-    let request = generate_request(messages, tools, top_p, temperature, &config.model_settings)
-        .context("Error generating request")?;
-
-    // Collect the private data transmitted in the request
-    extract_private_data(
-        sent_private_data,
-        request.headers(),
-        config.privacy_settings.request_topics_to_censor,
+    let stream = delta_sender.is_some();
+    let request = generate_request(provider, messages, tools, params, stream, keep_alive, &config.model_settings)
+        .context("Error generating request")
+        .context(ErrorStage::InvalidInput)?;
+
+    let response = send_request(request_sender, rounds, request).await?;
+
+    if let Some(delta_sender) = delta_sender {
+        return collect_streamed_message(provider, response.into_body(), delta_sender).await;
+    }
+
+    // Collect the body
+    let payload = read_body(response).await?;
+
+    let parsed = serde_json::from_str::<serde_json::Value>(&String::from_utf8_lossy(&payload))
+        .context("Error parsing the response")
+        .context(ErrorStage::ModelRequest)?;
+
+    // Pretty printing the response
+    debug!(
+        "Response: {}",
+        serde_json::to_string_pretty(&parsed).context("Error pretty printing the response")?
     );
 
+    debug!("Extracting the assistant's response...");
+
+    let received_assistant_message = provider
+        .assistant_message(&parsed)
+        .context("Error extracting the assistant's response")
+        .context(ErrorStage::ModelRequest)?;
+
+    Ok(ModelResponse {
+        message: received_assistant_message,
+        usage: provider.usage(&parsed).map(with_total_tokens),
+        metadata: provider.metadata(&parsed),
+        choices: provider
+            .choices(&parsed)
+            .context("Error extracting the choices")
+            .context(ErrorStage::ModelRequest)?,
+    })
+}
+
+/// Requests the model list of the API, counting the round in `rounds` like [`single_interaction_round`]
+pub(super) async fn model_list_round(
+    request_sender: &mut SendRequest<String>,
+    rounds: &mut usize,
+    config: &Config,
+    keep_alive: bool,
+) -> Result<ModelList> {
+    let provider = chat_provider(config.model_settings.api_settings.provider);
+
+    let request = generate_model_list_request(provider, keep_alive, &config.model_settings)
+        .context("Error generating request")
+        .context(ErrorStage::InvalidInput)?;
+
+    let response = send_request(request_sender, rounds, request).await?;
+    let payload = read_body(response).await?;
+
+    let parsed = serde_json::from_str::<serde_json::Value>(&String::from_utf8_lossy(&payload))
+        .context("Error parsing the response")
+        .context(ErrorStage::ModelRequest)?;
+
+    ModelList::from_response(&parsed).context(ErrorStage::ModelRequest)
+}
+
+/// Sends a request to the model API, turning an error status into a [`ModelApiError`]
+async fn send_request(
+    request_sender: &mut SendRequest<String>,
+    rounds: &mut usize,
+    request: hyper::Request<String>,
+) -> Result<hyper::Response<Incoming>> {
     debug!("Request: {:?}", request);
 
     debug!("Sending request to Model...");
+    *rounds += 1;
 
     let response = request_sender
         .send_request(request)
         .await
-        .context("Error sending request to Model")?;
+        .context("Error sending request to Model")
+        .context(ErrorStage::ModelRequest)?;
 
     debug!("Response: {:?}", response);
 
     if response.status() != StatusCode::OK {
-        // TODO - do a graceful shutdown
-        panic!(
-            "Request failed with status code: {}",
-            response.status()
-        );
+        let status = response.status();
+        let headers = response.headers().clone();
+
+        // Read the whole error body, so the connection stays usable and the transcript complete
+        let payload = read_body(response).await?;
+
+        return Err(ModelApiError::from_response(status, &headers, &payload).into());
     }
 
-    // Collect the received private data
-    extract_private_data(
-        recv_private_data,
-        response.headers(),
-        config.privacy_settings.response_topics_to_censor,
-    );
+    Ok(response)
+}
 
-    // Collect the body
-    let payload = response
+async fn read_body(response: hyper::Response<Incoming>) -> Result<Bytes> {
+    Ok(response
         .into_body()
         .collect()
         .await
-        .context("Error reading response body")?
-        .to_bytes();
+        .context("Error reading response body")
+        .context(ErrorStage::ModelRequest)?
+        .to_bytes())
+}
 
-    let parsed = serde_json::from_str::<serde_json::Value>(&String::from_utf8_lossy(&payload))
-        .context("Error parsing the response")?;
+/// Reads a `text/event-stream` body frame by frame, forwarding every delta as soon as it
+/// arrives, and returns the reassembled assistant message once the stream is over.
+async fn collect_streamed_message(
+    provider: &dyn ChatProvider,
+    mut body: Incoming,
+    delta_sender: &UnboundedSender<String>,
+) -> Result<ModelResponse> {
+    let mut parser = SseParser::default();
+    let mut accumulator = MessageAccumulator::default();
 
-    // Pretty printing the response
-    debug!(
-        "Response: {}",
-        serde_json::to_string_pretty(&parsed).context("Error pretty printing the response")?
-    );
+    // The body has to be read to the end even after `[DONE]`,
+    // otherwise the connection is not closed and the transcript is incomplete.
+    while let Some(frame) = body.frame().await {
+        let frame = frame
+            .context("Error reading response stream")
+            .context(ErrorStage::ModelRequest)?;
+        let Some(chunk) = frame.data_ref() else {
+            continue;
+        };
 
-    debug!("Extracting the assistant's response...");
+        for payload in parser.push(chunk) {
+            handle_stream_payload(provider, &payload, &mut accumulator, delta_sender)?;
+        }
+    }
+
+    if let Some(payload) = parser.finish() {
+        handle_stream_payload(provider, &payload, &mut accumulator, delta_sender)?;
+    }
+
+    let usage = accumulator.usage().map(with_total_tokens);
+    let metadata = accumulator.metadata().clone();
+    let received_assistant_message = accumulator.into_message();
+
+    debug!("Reassembled streamed response: {}", received_assistant_message);
+
+    let choice = json!({
+        "index": 0,
+        "message": received_assistant_message,
+        "finish_reason": metadata.finish_reason,
+    });
+
+    Ok(ModelResponse {
+        message: received_assistant_message,
+        usage,
+        metadata,
+        choices: vec![choice],
+    })
+}
+
+/// Completes a usage that only reports the prompt and completion tokens with their total
+fn with_total_tokens(mut usage: Value) -> Value {
+    let prompt_tokens = usage["prompt_tokens"].as_u64();
+    let completion_tokens = usage["completion_tokens"].as_u64();
+    if let (Some(usage), Some(prompt_tokens), Some(completion_tokens)) = (usage.as_object_mut(), prompt_tokens, completion_tokens) {
+        usage
+            .entry("total_tokens")
+            .or_insert_with(|| json!(prompt_tokens + completion_tokens));
+    }
+    usage
+}
+
+fn handle_stream_payload(
+    provider: &dyn ChatProvider,
+    payload: &str,
+    accumulator: &mut MessageAccumulator,
+    delta_sender: &UnboundedSender<String>,
+) -> Result<()> {
+    if payload == "[DONE]" {
+        return Ok(());
+    }
+
+    let parsed = serde_json::from_str::<serde_json::Value>(payload)
+        .context("Error parsing the streamed chunk")
+        .context(ErrorStage::ModelRequest)?;
 
-    let received_assistant_message = serde_json::json!(parsed["choices"][0]["message"]);
+    // The status was already 200 OK, the error comes as an event
+    if let Some(message) = provider.stream_error(&parsed) {
+        return Err(anyhow::anyhow!("The model API reported an error mid-stream: {message}"))
+            .context(ErrorStage::ModelRequest);
+    }
+
+    if let Some(usage) = provider.usage(&parsed) {
+        accumulator.push_usage(&usage);
+    }
+    accumulator.push_metadata(provider.metadata(&parsed));
+
+    let Some(delta) = provider.stream_delta(&parsed) else {
+        return Ok(());
+    };
 
-    Ok(received_assistant_message.to_string())
+    accumulator.push(&delta).context(ErrorStage::ModelRequest)?;
+
+    // The receiver going away only means nobody listens to the deltas anymore,
+    // the message still has to be collected for the proof.
+    let _ = delta_sender.send(delta.to_string());
+
+    Ok(())
 }
 
 fn generate_request(
+    provider: &dyn ChatProvider,
     messages: Vec<serde_json::Value>,
     tools: Vec<serde_json::Value>,
-    top_p: f64, temperature: f64,
+    params: &ChatRequestParams,
+    stream: bool,
+    keep_alive: bool,
     model_settings: &ModelSettings,
 ) -> Result<hyper::Request<String>> {
-    let mut json_body = serde_json::Map::new();
-    json_body.insert("model".to_string(), serde_json::json!(model_settings.id));
-    json_body.insert("messages".to_string(), serde_json::to_value(messages).context("Error serializing messages")?);
-    json_body.insert("tools".to_string(), serde_json::to_value(tools).context("Error serializing tools")?);
-    json_body.insert("top_p".to_string(), serde_json::json!(top_p));
-    json_body.insert("temperature".to_string(), serde_json::json!(temperature));
-    let json_body = serde_json::Value::Object(json_body);
+    let messages = with_setup_prompt(messages, model_settings);
+    let json_body = provider.request_body(model_settings, messages, tools, params, stream)?;
+
+    let api_settings = &model_settings.api_settings;
 
     // Build the HTTP request to send the prompt to Model's API
-    hyper::Request::builder()
-        .method(Method::POST)
-        .uri(model_settings.api_settings.inference_route)
-        .header(HOST, model_settings.api_settings.server_domain)
-        .header("Accept-Encoding", "identity")
-        .header(CONNECTION, "close")
-        .header(CONTENT_TYPE, "application/json")
-        .header(
-            AUTHORIZATION,
-            format!("Bearer {}", model_settings.api_settings.api_key),
-        )
+    let request_builder = request_builder(provider, Method::POST, &api_settings.inference_route, keep_alive, model_settings)
+        .header(CONTENT_TYPE, "application/json");
+
+    request_builder
         .body(json_body.to_string())
         .context("Error building the request")
-}
\ No newline at end of file
+}
+
+/// Puts the setup prompt, if any, ahead of the conversation as a `system` message
+fn with_setup_prompt(messages: Vec<Value>, model_settings: &ModelSettings) -> Vec<Value> {
+    match &model_settings.setup_prompt {
+        Some(setup_prompt) => std::iter::once(json!({ "role": "system", "content": setup_prompt }))
+            .chain(messages)
+            .collect(),
+        None => messages,
+    }
+}
+
+fn generate_model_list_request(
+    provider: &dyn ChatProvider,
+    keep_alive: bool,
+    model_settings: &ModelSettings,
+) -> Result<hyper::Request<String>> {
+    let route = &model_settings.api_settings.model_list_route;
+
+    request_builder(provider, Method::GET, route, keep_alive, model_settings)
+        .body(String::new())
+        .context("Error building the request")
+}
+
+/// Request to the model API with the host, connection and authentication headers set
+fn request_builder(
+    provider: &dyn ChatProvider,
+    method: Method,
+    route: &str,
+    keep_alive: bool,
+    model_settings: &ModelSettings,
+) -> hyper::http::request::Builder {
+    let api_settings = &model_settings.api_settings;
+
+    let mut request_builder = hyper::Request::builder()
+        .method(method)
+        .uri(route)
+        .header(HOST, api_settings.host_header())
+        .header("Accept-Encoding", "identity")
+        .header(CONNECTION, if keep_alive { "keep-alive" } else { "close" });
+
+    for (name, value) in provider.auth_headers(&api_settings.api_key) {
+        request_builder = request_builder.header(name, value);
+    }
+
+    for (name, value) in &api_settings.extra_headers {
+        request_builder = request_builder.header(name.as_str(), value.as_str());
+    }
+
+    request_builder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ModelApiSettings;
+    use crate::conversation::ChatMessage;
+    use crate::session::validate_request;
+
+    fn anthropic_settings() -> ModelSettings {
+        ModelSettings {
+            api_settings: ModelApiSettings::new(String::new()),
+            id: "claude-3-5-sonnet".to_string(),
+            setup_prompt: None,
+            validate_model: false,
+        }
+    }
+
+    #[test]
+    fn test_anthropic_params() -> Result<()> {
+        let params = ChatRequestParams {
+            temperature: Some(0.7),
+            max_tokens: Some(256),
+            stop: Some(vec!["END".to_string()]),
+            tool_choice: Some(json!("required")),
+            ..ChatRequestParams::default()
+        };
+        let body = AnthropicProvider.request_body(
+            &anthropic_settings(),
+            vec![json!({ "role": "user", "content": "hi" })],
+            vec![],
+            &params,
+            false,
+        )?;
+        assert_eq!(body["max_tokens"], 256);
+        assert_eq!(body["temperature"], 0.7);
+        assert_eq!(body["stop_sequences"], json!(["END"]));
+        assert_eq!(body["tool_choice"], json!({ "type": "any" }));
+        assert!(body.get("top_p").is_none());
+
+        let seeded = ChatRequestParams { seed: Some(7), ..ChatRequestParams::default() };
+        assert!(AnthropicProvider
+            .request_body(&anthropic_settings(), vec![], vec![], &seeded, false)
+            .is_err());
+
+        // Valid for OpenAI, out of the Anthropic range
+        let hot = ChatRequestParams { temperature: Some(1.5), ..ChatRequestParams::default() };
+        assert!(OpenAiProvider.validate_params(&hot).is_ok());
+        let error = validate_request(&[ChatMessage::user("hi")], &[], &hot, ApiProvider::Anthropic).unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(ErrorStage::InvalidInput)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_openai_completion_metadata() -> Result<()> {
+        let response = json!({
+            "id": "chatcmpl-1", "object": "chat.completion", "created": 1730000000, "model": "gpt-4o-2024-08-06",
+            "system_fingerprint": "fp_1",
+            "choices": [
+                { "index": 0, "message": { "role": "assistant", "content": "Hi" }, "finish_reason": "stop" },
+                { "index": 1, "message": { "role": "assistant", "content": "Hello" }, "finish_reason": "length" }
+            ],
+            "usage": { "prompt_tokens": 5, "completion_tokens": 2, "total_tokens": 7 }
+        });
+
+        let metadata = OpenAiProvider.metadata(&response);
+        assert_eq!(
+            metadata,
+            CompletionMetadata {
+                id: Some("chatcmpl-1".to_string()),
+                created: Some(1730000000),
+                model: Some("gpt-4o-2024-08-06".to_string()),
+                system_fingerprint: Some("fp_1".to_string()),
+                finish_reason: Some("stop".to_string()),
+            }
+        );
+        assert_eq!(OpenAiProvider.choices(&response)?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_model_list() -> Result<()> {
+        let list = ModelList::from_response(&json!({
+            "object": "list",
+            "data": [{ "id": "gpt-4o", "object": "model" }, { "id": "gpt-4o-mini", "object": "model" }]
+        }))?;
+        assert_eq!(list.ids(), ["gpt-4o", "gpt-4o-mini"]);
+        assert!(list.contains("gpt-4o-mini"));
+        assert!(!list.contains("gpt-4"));
+
+        assert_eq!(ModelList::from_response(&json!([{ "id": "llama-3" }]))?.ids(), ["llama-3"]);
+        assert!(ModelList::from_response(&json!({ "error": "not found" })).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_anthropic_round_trip() -> Result<()> {
+        let messages = vec![
+            json!({ "role": "system", "content": "Be brief." }),
+            json!({ "role": "user", "content": "whats the weather in sf?" }),
+            json!({ "role": "assistant", "content": null, "tool_calls": [{
+                "id": "toolu_1", "type": "function",
+                "function": { "name": "tavily_search_results_json", "arguments": "{\"query\":\"sf weather\"}" }
+            }] }),
+            json!({ "role": "tool", "tool_call_id": "toolu_1", "content": "Sunny, 18C" }),
+        ];
+
+        let (system, converted) = anthropic_messages(messages)?;
+        assert_eq!(system.as_deref(), Some("Be brief."));
+        assert_eq!(converted.len(), 3);
+        assert_eq!(converted[1]["content"][0]["type"], "tool_use");
+        assert_eq!(converted[1]["content"][0]["input"]["query"], "sf weather");
+        assert_eq!(converted[2]["role"], "user");
+        assert_eq!(converted[2]["content"][0]["tool_use_id"], "toolu_1");
+
+        let message = AnthropicProvider.assistant_message(&json!({
+            "content": [
+                { "type": "text", "text": "Let me check." },
+                { "type": "tool_use", "id": "toolu_2", "name": "tavily_search_results_json", "input": { "query": "sf" } }
+            ]
+        }))?;
+        assert_eq!(message["content"], "Let me check.");
+        assert_eq!(message["tool_calls"][0]["function"]["arguments"], "{\"query\":\"sf\"}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_streamed_anthropic_usage() {
+        let mut accumulator = MessageAccumulator::default();
+        for event in [
+            json!({ "type": "message_start", "message": {
+                "id": "msg_1", "model": "claude-3-5-sonnet", "usage": { "input_tokens": 25, "output_tokens": 1 }
+            } }),
+            json!({ "type": "message_delta", "delta": { "stop_reason": "tool_use" }, "usage": { "output_tokens": 15 } }),
+        ] {
+            let usage = AnthropicProvider.usage(&event).unwrap();
+            accumulator.push_usage(&usage);
+            accumulator.push_metadata(AnthropicProvider.metadata(&event));
+        }
+
+        assert_eq!(accumulator.metadata().id.as_deref(), Some("msg_1"));
+        assert_eq!(accumulator.metadata().model.as_deref(), Some("claude-3-5-sonnet"));
+        assert_eq!(accumulator.metadata().finish_reason.as_deref(), Some("tool_calls"));
+
+        let usage = accumulator.usage().map(with_total_tokens).unwrap();
+        assert_eq!(usage, json!({ "prompt_tokens": 25, "completion_tokens": 15, "total_tokens": 40 }));
+        assert_eq!(OpenAiProvider.usage(&json!({ "usage": null })), None);
+    }
+
+    /// Feeds a streamed body to the payload handling of [`collect_streamed_message`]
+    fn collect_stream(provider: &dyn ChatProvider, body: &str) -> Result<Value> {
+        let (delta_sender, _delta_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let mut parser = SseParser::default();
+        let mut accumulator = MessageAccumulator::default();
+
+        let mut payloads = parser.push(body.as_bytes());
+        payloads.extend(parser.finish());
+        for payload in payloads {
+            handle_stream_payload(provider, &payload, &mut accumulator, &delta_sender)?;
+        }
+        Ok(accumulator.into_message())
+    }
+
+    #[test]
+    fn test_openai_stream_error_event() {
+        let body = concat!(
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"Hi\"}}]}\n\n",
+            "data: {\"error\":{\"message\":\"The server had an error\",\"type\":\"server_error\"}}\n\n",
+            "data: [DONE]\n\n",
+        );
+
+        let error = collect_stream(&OpenAiProvider, body).unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(ErrorStage::ModelRequest)));
+        assert!(format!("{error:#}").contains("The server had an error"), "{error:#}");
+    }
+
+    #[test]
+    fn test_anthropic_stream_error_event() {
+        let body = concat!(
+            "event: message_start\n",
+            "data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\"}}\n\n",
+            "event: error\n",
+            "data: {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n\n",
+        );
+
+        let error = collect_stream(&AnthropicProvider, body).unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(ErrorStage::ModelRequest)));
+        assert!(format!("{error:#}").contains("Overloaded"), "{error:#}");
+    }
+}
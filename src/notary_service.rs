@@ -0,0 +1,177 @@
+use crate::config::{DEFAULT_MAX_RECV_DATA, DEFAULT_MAX_SENT_DATA};
+use anyhow::{bail, Context, Result};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use p256::ecdsa::SigningKey;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tlsn_core::SessionHeader;
+use tlsn_verifier::tls::{Verifier, VerifierConfig};
+use tracing::{debug, info};
+
+/// Longest handshake line accepted, the messages are tiny JSON objects
+const MAX_HANDSHAKE_LINE: usize = 1024;
+
+/// Time a client has to send its session request, so idle connections do not hold a session slot
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest a whole session may take, from the connection to the signed header
+pub const DEFAULT_SESSION_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Opens a session with a standalone notary service (`tlsn-langchain-notary`).
+///
+/// The prover sends the transcript sizes it needs, the notary answers with a session id,
+/// and the MPC protocol runs on the same connection right after.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionRequest {
+    pub max_sent_data: usize,
+    pub max_recv_data: usize,
+}
+
+/// Answer of the notary service to a [`SessionRequest`]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum SessionResponse {
+    Accepted { session_id: String },
+    Rejected { reason: String },
+}
+
+/// Limits a notary service enforces on every session
+#[derive(Debug, Clone)]
+pub struct ServiceLimits {
+    pub max_sent_data: usize,
+    pub max_recv_data: usize,
+    /// Applied by [`serve_session`] to reading the session request
+    pub handshake_timeout: Duration,
+    /// Applied by the service to the whole connection
+    pub session_timeout: Duration,
+}
+
+impl Default for ServiceLimits {
+    fn default() -> Self {
+        Self {
+            max_sent_data: DEFAULT_MAX_SENT_DATA,
+            max_recv_data: DEFAULT_MAX_RECV_DATA,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            session_timeout: DEFAULT_SESSION_TIMEOUT,
+        }
+    }
+}
+
+/// Prover side of the handshake, returns the session id to configure the prover with
+pub async fn request_session<T: AsyncRead + AsyncWrite + Unpin>(
+    io: &mut T,
+    max_sent_data: usize,
+    max_recv_data: usize,
+) -> Result<String> {
+    let request = SessionRequest {
+        max_sent_data,
+        max_recv_data,
+    };
+    write_line(io, &serde_json::to_vec(&request)?).await?;
+
+    let response = read_line(io).await?;
+    match serde_json::from_slice(&response).context("Error parsing the notary response")? {
+        SessionResponse::Accepted { session_id } => Ok(session_id),
+        SessionResponse::Rejected { reason } => bail!("The notary rejected the session: {reason}"),
+    }
+}
+
+/// Notary side of a session: answers the handshake and notarises the TLS session of the prover
+pub async fn serve_session<T: AsyncRead + AsyncWrite + Send + Unpin + 'static>(
+    mut io: T,
+    signing_key: &SigningKey,
+    limits: &ServiceLimits,
+) -> Result<SessionHeader> {
+    let request = tokio::time::timeout(limits.handshake_timeout, read_line(&mut io))
+        .await
+        .context("The client did not send its session request in time")??;
+    let request: SessionRequest =
+        serde_json::from_slice(&request).context("Error parsing the session request")?;
+
+    if request.max_sent_data > limits.max_sent_data || request.max_recv_data > limits.max_recv_data {
+        let reason = format!(
+            "Requested {} sent / {} received bytes, the limits are {} / {}",
+            request.max_sent_data, request.max_recv_data, limits.max_sent_data, limits.max_recv_data
+        );
+        let response = SessionResponse::Rejected {
+            reason: reason.clone(),
+        };
+        write_line(&mut io, &serde_json::to_vec(&response)?).await?;
+        bail!(reason);
+    }
+
+    let session_id = uuid::Uuid::new_v4().to_string();
+    info!(
+        session_id = %session_id,
+        max_sent_data = request.max_sent_data,
+        max_recv_data = request.max_recv_data,
+        "Session accepted"
+    );
+
+    let response = SessionResponse::Accepted {
+        session_id: session_id.clone(),
+    };
+    write_line(&mut io, &serde_json::to_vec(&response)?).await?;
+
+    let config = VerifierConfig::builder()
+        .id(session_id)
+        .max_sent_data(request.max_sent_data)
+        .max_recv_data(request.max_recv_data)
+        .build()
+        .context("Failed to build verifier config")?;
+
+    Verifier::new(config)
+        .notarize::<_, p256::ecdsa::Signature>(io, signing_key)
+        .await
+        .context("Error notarizing the session")
+}
+
+async fn write_line<T: AsyncWrite + Unpin>(io: &mut T, message: &[u8]) -> Result<()> {
+    io.write_all(message).await?;
+    io.write_all(b"\n").await?;
+    io.flush().await.context("Error sending the handshake")
+}
+
+/// Reads one handshake line byte by byte, so nothing of the MPC protocol that follows is consumed
+async fn read_line<T: AsyncRead + Unpin>(io: &mut T) -> Result<Vec<u8>> {
+    let mut line = vec![];
+    let mut byte = [0u8; 1];
+    loop {
+        io.read_exact(&mut byte)
+            .await
+            .context("Connection closed during the handshake")?;
+        if byte[0] == b'\n' {
+            debug!("Handshake line: {}", String::from_utf8_lossy(&line));
+            return Ok(line);
+        }
+        if line.len() == MAX_HANDSHAKE_LINE {
+            bail!("Handshake line is too long");
+        }
+        line.push(byte[0]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_util::compat::TokioAsyncReadCompatExt;
+
+    #[tokio::test]
+    async fn test_oversized_session_is_rejected() -> Result<()> {
+        let (prover_socket, notary_socket) = tokio::io::duplex(1 << 12);
+        let (mut prover_socket, notary_socket) = (prover_socket.compat(), notary_socket.compat());
+
+        let limits = ServiceLimits {
+            max_sent_data: 1 << 12,
+            max_recv_data: 1 << 14,
+            ..ServiceLimits::default()
+        };
+        let signing_key = SigningKey::random(&mut rand::rngs::OsRng);
+        let notary = tokio::spawn(async move { serve_session(notary_socket, &signing_key, &limits).await });
+
+        let error = request_session(&mut prover_socket, 1 << 12, 1 << 20).await.unwrap_err();
+        assert!(error.to_string().contains("rejected"));
+        assert!(notary.await?.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,491 @@
+use crate::body_disclosure::{http_body_contents, http_body_revealed_prefixes, revealed_prefix_values, JsonPath};
+use crate::config::server_root_cert_store;
+use crate::errors::VerificationFailure;
+use crate::model_interactions::detect_provider;
+use crate::py_errors::{InvalidInputError, ProofBuildError};
+use crate::streaming::{MessageAccumulator, SseParser};
+use anyhow::{Context, Result};
+use p256::pkcs8::{DecodePublicKey, EncodePublicKey, LineEnding};
+use pyo3::types::PyBytes;
+use pyo3::{pyclass, pymethods, PyResult, Python};
+use serde_json::Value;
+use std::ops::Range;
+use std::sync::Arc;
+use tls_core::verify::WebPkiVerifier;
+use tlsn_core::proof::{SessionProof, SubstringsProof, TlsProof};
+use tlsn_core::{RedactedTranscript, SessionHeader};
+
+/// Byte used in place of the redacted parts of the revealed transcripts
+pub const REDACTED_BYTE: u8 = b'X';
+
+/// Outcome of a successful proof verification
+#[pyclass(get_all, frozen)]
+#[derive(Debug, Clone)]
+pub struct VerifiedProof {
+    /// DNS name of the server the notarised session was held with
+    pub server_name: String,
+    /// Notarisation time, in seconds since the UNIX epoch
+    pub time: u64,
+    /// Revealed request bytes, with redacted bytes replaced by [`REDACTED_BYTE`]
+    pub sent: String,
+    /// Revealed response bytes, with redacted bytes replaced by [`REDACTED_BYTE`]
+    pub recv: String,
+    /// Byte ranges of `sent` that are redacted
+    pub sent_redacted: Vec<(usize, usize)>,
+    /// Byte ranges of `recv` that are redacted
+    pub recv_redacted: Vec<(usize, usize)>,
+}
+
+impl VerifiedProof {
+    /// Model id of the first request that reveals its top level `model` field.
+    ///
+    /// Only the part of a request before its first redaction is read, so a value that merely looks like
+    /// the field (e.g. inside a tool schema) does not pass for it.
+    pub fn model_id(&self) -> Option<String> {
+        self.revealed_request_values("model")
+            .into_iter()
+            .find_map(|model| model.as_str().map(str::to_string))
+    }
+
+    /// System prompt of the first request that reveals one: its first message if that is a system
+    /// message, or its Anthropic `system` field.
+    ///
+    /// As with [`VerifiedProof::model_id`], the prompt must be revealed along with everything before it,
+    /// as `reveal_system_prompt_only` does.
+    pub fn system_prompt(&self) -> Option<String> {
+        let first_message = self
+            .revealed_request_values("messages[0]")
+            .into_iter()
+            .filter(|message| message["role"] == "system")
+            .find_map(|message| message["content"].as_str().map(str::to_string));
+
+        first_message.or_else(|| {
+            self.revealed_request_values("system")
+                .into_iter()
+                .find_map(|system| system.as_str().map(str::to_string))
+        })
+    }
+
+    /// Assistant messages of the revealed responses, as OpenAI `choices[0].message` objects.
+    ///
+    /// Responses whose redactions make them unreadable are skipped.
+    pub fn assistant_messages(&self) -> Vec<Value> {
+        http_body_contents(self.recv.as_bytes(), true)
+            .into_iter()
+            .filter_map(|(body, event_stream)| {
+                if event_stream {
+                    return streamed_assistant_message(&body);
+                }
+                let response = serde_json::from_slice::<Value>(&body).ok()?;
+                detect_provider(&response).assistant_message(&response).ok()
+            })
+            .collect()
+    }
+
+    /// Values at `path` of the requests whose revealed prefix holds them
+    fn revealed_request_values(&self, path: &str) -> Vec<Value> {
+        let path = JsonPath::parse(path).expect("The request paths are valid");
+        let redacted: Vec<Range<usize>> = self.sent_redacted.iter().map(|&(start, end)| start..end).collect();
+
+        http_body_revealed_prefixes(self.sent.as_bytes(), &redacted, false)
+            .into_iter()
+            .filter_map(|(body, revealed_len)| {
+                let (_, value) = revealed_prefix_values(&body, revealed_len)
+                    .into_iter()
+                    .find(|(value_path, _)| path.matches(value_path))?;
+                serde_json::from_slice(&body[value]).ok()
+            })
+            .collect()
+    }
+}
+
+fn streamed_assistant_message(body: &[u8]) -> Option<Value> {
+    let mut parser = SseParser::default();
+    let mut payloads = parser.push(body);
+    payloads.extend(parser.finish());
+
+    let mut accumulator = MessageAccumulator::default();
+    let mut any_delta = false;
+    for payload in payloads {
+        let Ok(event) = serde_json::from_str::<Value>(&payload) else {
+            continue;
+        };
+        if let Some(delta) = detect_provider(&event).stream_delta(&event) {
+            any_delta |= accumulator.push(&delta).is_ok();
+        }
+    }
+
+    any_delta.then(|| accumulator.into_message())
+}
+
+#[pymethods]
+impl VerifiedProof {
+    /// Model id of the first request that reveals its top level `model` field
+    #[pyo3(name = "model_id")]
+    fn py_model_id(&self) -> Option<String> {
+        self.model_id()
+    }
+
+    /// System prompt of the first request that reveals it along with everything before it
+    #[pyo3(name = "system_prompt")]
+    fn py_system_prompt(&self) -> Option<String> {
+        self.system_prompt()
+    }
+}
+
+/// Verifies a proof produced by `notarised_model_request`.
+///
+/// Checks the notary signature over the session header, the server certificate chain
+/// against the default web roots and that the session was held with `expected_server_name`.
+pub fn verify_proof(
+    proof_json: &str,
+    notary_pubkey_pem: &str,
+    expected_server_name: &str,
+) -> Result<VerifiedProof> {
+    verify_proof_with_root_ca(proof_json, notary_pubkey_pem, expected_server_name, None)
+}
+
+/// Same as [`verify_proof`], but the server certificate chain may also end at one of
+/// the PEM encoded `root_ca_pem` certificates (e.g. of a private PKI)
+pub fn verify_proof_with_root_ca(
+    proof_json: &str,
+    notary_pubkey_pem: &str,
+    expected_server_name: &str,
+    root_ca_pem: Option<&str>,
+) -> Result<VerifiedProof> {
+    let proof: TlsProof = serde_json::from_str(proof_json)
+        .context(VerificationFailure::MalformedProof)
+        .context("Error parsing the proof")?;
+    let notary_pubkey = p256::PublicKey::from_public_key_pem(notary_pubkey_pem)
+        .context("Error parsing the notary public key")?;
+    let cert_verifier = WebPkiVerifier::new(server_root_cert_store(root_ca_pem)?, None);
+
+    let TlsProof { session, substrings } = proof;
+
+    // Verify the session header is signed by the notary and the handshake matches the server
+    session
+        .verify(notary_pubkey, &cert_verifier)
+        .context(VerificationFailure::InvalidSignature)
+        .context("Error verifying the session proof")?;
+
+    let SessionProof {
+        header,
+        session_info,
+        ..
+    } = session;
+
+    let server_name = session_info.server_name.as_str();
+    if server_name != expected_server_name {
+        return Err(anyhow::Error::new(VerificationFailure::WrongServer).context(format!(
+            "Proof is for server `{}`, expected `{}`",
+            server_name, expected_server_name
+        )));
+    }
+
+    let ((sent, sent_redacted), (recv, recv_redacted)) = revealed_transcripts(substrings, &header)?;
+
+    Ok(VerifiedProof {
+        server_name: server_name.to_string(),
+        time: header.time(),
+        sent,
+        recv,
+        sent_redacted,
+        recv_redacted,
+    })
+}
+
+/// A revealed transcript as text, and the byte ranges of the text that are redacted
+type RevealedText = (String, Vec<(usize, usize)>);
+
+/// Verifies the revealed substrings against the commitments in the session header
+fn revealed_transcripts(substrings: SubstringsProof, header: &SessionHeader) -> Result<(RevealedText, RevealedText)> {
+    let (sent, recv) = substrings
+        .verify(header)
+        .context(VerificationFailure::MalformedProof)
+        .context("Error verifying the substrings proof")?;
+
+    Ok((revealed_text(sent), revealed_text(recv)))
+}
+
+/// Bytes that are not valid UTF-8 are replaced by [`REDACTED_BYTE`] and counted as redacted too, so the
+/// offsets in the text are those of the transcript
+fn revealed_text(mut transcript: RedactedTranscript) -> RevealedText {
+    transcript.set_redacted(REDACTED_BYTE);
+    let mut redacted: Vec<Range<usize>> = transcript.redacted().iter_ranges().collect();
+    let mut data = transcript.data().to_vec();
+
+    let mut pos = 0;
+    while let Err(e) = std::str::from_utf8(&data[pos..]) {
+        let start = pos + e.valid_up_to();
+        let end = start + e.error_len().unwrap_or(data.len() - start);
+        data[start..end].fill(REDACTED_BYTE);
+        redacted.push(start..end);
+        pos = end;
+    }
+    redacted.sort_by_key(|range| range.start);
+
+    let text = String::from_utf8(data).expect("Invalid bytes are replaced");
+    (text, redacted.into_iter().map(|range| (range.start, range.end)).collect())
+}
+
+/// A proof of a notarised session with the model API.
+///
+/// Reading it does not check the notary signature, use `verify_proof` for that.
+#[pyclass(frozen)]
+#[derive(Debug, Clone)]
+pub struct Proof {
+    proof: Arc<TlsProof>,
+    server_name: String,
+    time: u64,
+    sent: String,
+    recv: String,
+    notary_public_key: Option<String>,
+}
+
+impl Proof {
+    pub fn new(proof: TlsProof) -> Result<Self> {
+        let proof_bytes = bincode::serialize(&proof).context("Error serializing the proof")?;
+        Self::with_revealed_transcripts(proof, &proof_bytes)
+    }
+
+    /// Decodes the compact binary (bincode) encoding of [`Proof::to_bytes`]
+    pub fn from_bytes(proof_bytes: &[u8]) -> Result<Self> {
+        let proof = bincode::deserialize(proof_bytes).context("Error parsing the proof")?;
+        Self::with_revealed_transcripts(proof, proof_bytes)
+    }
+
+    /// `SubstringsProof` is not `Clone` and verifying it consumes it, so the revealed transcripts are read
+    /// from a second decoding of `proof_bytes`, the encoding of `proof`
+    fn with_revealed_transcripts(proof: TlsProof, proof_bytes: &[u8]) -> Result<Self> {
+        let TlsProof { session, substrings } =
+            bincode::deserialize::<TlsProof>(proof_bytes).context("Error parsing the proof")?;
+        let ((sent, _), (recv, _)) = revealed_transcripts(substrings, &session.header)?;
+
+        Ok(Self {
+            server_name: proof.session.session_info.server_name.as_str().to_string(),
+            time: proof.session.header.time(),
+            sent,
+            recv,
+            proof: Arc::new(proof),
+            notary_public_key: None,
+        })
+    }
+
+    /// Attaches the public key of the notary that signed the proof (e.g. the ephemeral dummy notary)
+    pub fn with_notary_public_key(self, notary_public_key: p256::PublicKey) -> Result<Self> {
+        let notary_public_key = notary_public_key
+            .to_public_key_pem(LineEnding::LF)
+            .context("Error encoding the notary public key")?;
+
+        Ok(Self {
+            notary_public_key: Some(notary_public_key),
+            ..self
+        })
+    }
+
+    pub fn tls_proof(&self) -> &TlsProof {
+        &self.proof
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self.tls_proof()).context("Error serializing the proof")
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        bincode::serialize(self.tls_proof()).context("Error serializing the proof")
+    }
+}
+
+#[pymethods]
+impl Proof {
+    #[staticmethod]
+    #[pyo3(name = "from_json")]
+    fn py_from_json(proof_json: &str) -> PyResult<Self> {
+        let proof = serde_json::from_str(proof_json)
+            .map_err(|e| InvalidInputError::new_err(format!("Error parsing the proof: {e}")))?;
+        Self::new(proof).map_err(|e| InvalidInputError::new_err(format!("{e:#}")))
+    }
+
+    #[staticmethod]
+    #[pyo3(name = "from_bytes")]
+    fn py_from_bytes(proof_bytes: &[u8]) -> PyResult<Self> {
+        Self::from_bytes(proof_bytes).map_err(|e| InvalidInputError::new_err(format!("{e:#}")))
+    }
+
+    /// DNS name of the server the notarised session was held with
+    #[getter]
+    fn server_name(&self) -> &str {
+        &self.server_name
+    }
+
+    /// Notarisation time, in seconds since the UNIX epoch
+    #[getter]
+    fn time(&self) -> u64 {
+        self.time
+    }
+
+    /// PEM encoded public key of the notary, when the session was notarised by the dummy notary
+    #[getter]
+    fn notary_public_key(&self) -> Option<&str> {
+        self.notary_public_key.as_deref()
+    }
+
+    /// Revealed request bytes, with redacted bytes replaced by [`REDACTED_BYTE`]
+    fn revealed_sent(&self) -> &str {
+        &self.sent
+    }
+
+    /// Revealed response bytes, with redacted bytes replaced by [`REDACTED_BYTE`]
+    fn revealed_recv(&self) -> &str {
+        &self.recv
+    }
+
+    #[pyo3(name = "to_json")]
+    fn py_to_json(&self) -> PyResult<String> {
+        self.to_json().map_err(|e| ProofBuildError::new_err(format!("{e:#}")))
+    }
+
+    /// Compact binary (bincode) encoding of the proof
+    #[pyo3(name = "to_bytes")]
+    fn py_to_bytes<'p>(&self, py: Python<'p>) -> PyResult<&'p PyBytes> {
+        let bytes = self.to_bytes().map_err(|e| ProofBuildError::new_err(format!("{e:#}")))?;
+        Ok(PyBytes::new(py, &bytes))
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Proof(server_name={:?}, time={})", self.server_name, self.time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat_params::ChatRequestParams;
+    use crate::conversation::ChatMessage;
+    use crate::mock_server::{MockModelServer, MOCK_SERVER_DOMAIN};
+    use crate::setup_notary::notary_public_key;
+    use crate::{default_config, notarised_model_request};
+    use regex::Regex;
+
+    #[test]
+    fn test_verify_rejects_malformed_proof() {
+        let notary_pubkey_pem = include_str!("../tlsn/notary.pub");
+
+        let error = verify_proof("{}", notary_pubkey_pem, "api.red-pill.ai").unwrap_err();
+        assert_eq!(error.to_string(), "Error parsing the proof");
+        assert_eq!(
+            error.downcast_ref::<VerificationFailure>(),
+            Some(&VerificationFailure::MalformedProof)
+        );
+    }
+
+    /// Request transcript holding `body`, with the runs of `X` of the body and the authorization header redacted
+    fn redacted_request(body: &str) -> (String, Vec<(usize, usize)>) {
+        let sent = format!(
+            "POST /v1/chat/completions HTTP/1.1\r\nauthorization: XXXXXXXX\r\ncontent-length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let redacted = Regex::new("X+")
+            .unwrap()
+            .find_iter(&sent)
+            .map(|run| (run.start(), run.end()))
+            .collect();
+        (sent, redacted)
+    }
+
+    fn verified_request(body: &str) -> VerifiedProof {
+        let (sent, sent_redacted) = redacted_request(body);
+        VerifiedProof {
+            server_name: "api.red-pill.ai".to_string(),
+            time: 0,
+            sent,
+            recv: String::new(),
+            sent_redacted,
+            recv_redacted: vec![],
+        }
+    }
+
+    #[test]
+    fn test_revealed_model_and_message() {
+        let response_body = r#"{"choices":[{"message":{"role":"assistant","content":"Hi Bob!"}}]}"#;
+        let verified = VerifiedProof {
+            recv: format!(
+                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{}",
+                response_body.len(),
+                response_body
+            ),
+            ..verified_request(r#"{"model":"gpt-4o","messages":[{"role":"user","content":"XXXXX"}]}"#)
+        };
+
+        assert_eq!(verified.model_id().as_deref(), Some("gpt-4o"));
+        let messages = verified.assistant_messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0]["content"], "Hi Bob!");
+    }
+
+    #[test]
+    fn test_revealed_system_prompt() {
+        let openai = r#"{"model":"gpt-4o","messages":[{"role":"system","content":"Answer in French."},XXXXXXXXXX]}"#;
+        let verified = verified_request(openai);
+        assert_eq!(verified.system_prompt().as_deref(), Some("Answer in French."));
+        assert_eq!(verified.model_id().as_deref(), Some("gpt-4o"));
+
+        let anthropic = r#"{"model":"claude-3-5-sonnet","max_tokens":4096,"system":"Answer in French.","messages":XXXXXXXXXXXX}"#;
+        assert_eq!(verified_request(anthropic).system_prompt().as_deref(), Some("Answer in French."));
+
+        let user_only = r#"{"model":"gpt-4o","messages":[{"role":"user","content":"XXXXXXX"}]}"#;
+        assert_eq!(verified_request(user_only).system_prompt(), None);
+    }
+
+    #[test]
+    fn test_decoy_system_prompt_is_not_trusted() {
+        // The real prompt is redacted while a system message in a tool schema is revealed
+        let openai = concat!(
+            r#"{"model":"gpt-4o","messages":[{"role":"system","content":"XXXXXXXXXXXX"}],"#,
+            r#""tools":[{"type":"function","function":"#,
+            r#"{"name":"f","parameters":{"role":"system","content":"Answer in French."}}}]}"#,
+        );
+        let verified = verified_request(openai);
+        assert_eq!(verified.system_prompt(), None);
+        assert_eq!(verified.model_id().as_deref(), Some("gpt-4o"));
+
+        let hidden_model = r#"{"model":"XXXXXX","messages":[{"role":"user","content":"{\"model\":\"gpt-4o\"}"}]}"#;
+        assert_eq!(verified_request(hidden_model).model_id(), None);
+
+        let anthropic = concat!(
+            r#"{"model":"claude-3-5-sonnet","max_tokens":4096,"system":"XXXXXXXX","messages":[],"#,
+            r#""tools":[{"name":"f","input_schema":{"system":"Answer in French."}}]}"#,
+        );
+        let verified = verified_request(anthropic);
+        assert_eq!(verified.system_prompt(), None);
+        assert_eq!(verified.model_id().as_deref(), Some("claude-3-5-sonnet"));
+    }
+
+    #[tokio::test]
+    async fn test_proof_bytes_round_trip() -> Result<()> {
+        let server = MockModelServer::start().await?;
+        let config = default_config("mock-gpt".to_string(), server.api_settings());
+        let notary_pubkey_pem = notary_public_key(&config.notary_settings)?.to_public_key_pem(LineEnding::LF)?;
+        let (_, proof) =
+            notarised_model_request(config, vec![ChatMessage::user("hi")], vec![], ChatRequestParams::default()).await?;
+
+        let proof_bytes = proof.to_bytes()?;
+        let restored = Proof::from_bytes(&proof_bytes)?;
+        assert_eq!(restored.to_bytes()?, proof_bytes);
+        assert_eq!(restored.to_json()?, proof.to_json()?);
+        assert_eq!((&restored.sent, &restored.recv), (&proof.sent, &proof.recv));
+        assert_eq!((&restored.server_name, restored.time), (&proof.server_name, proof.time));
+
+        let verified = verify_proof_with_root_ca(
+            &restored.to_json()?,
+            &notary_pubkey_pem,
+            MOCK_SERVER_DOMAIN,
+            Some(&server.root_ca_pem),
+        )?;
+        assert_eq!((verified.sent, verified.recv), (proof.sent, proof.recv));
+
+        assert!(Proof::from_bytes(&proof_bytes[..proof_bytes.len() / 2]).is_err());
+        Ok(())
+    }
+}
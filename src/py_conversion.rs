@@ -0,0 +1,293 @@
+//! Conversion of the messages and tools passed from Python into the OpenAI schemas
+
+use crate::conversation::{ChatMessage, ToolDefinition};
+use anyhow::{anyhow, bail, Context, Result};
+use pyo3::types::{PyBool, PyDict, PyFloat, PyList, PyLong, PyString, PyTuple};
+use pyo3::PyAny;
+use serde_json::{json, Map, Value};
+
+/// Converts the messages and tools of a request.
+///
+/// A message is a dict, a JSON string or a LangChain message (anything with `model_dump()`, `dict()`
+/// or `to_json()`), a tool is a dict or a JSON string. Errors name the index and field at fault.
+pub(crate) fn py_conversation(messages: &[&PyAny], tools: &[&PyAny]) -> Result<(Vec<ChatMessage>, Vec<ToolDefinition>)> {
+    let messages = messages
+        .iter()
+        .enumerate()
+        .map(|(index, message)| py_message(message).with_context(|| format!("Invalid message at index {index}")))
+        .collect::<Result<Vec<_>>>()?;
+
+    let tools = tools
+        .iter()
+        .enumerate()
+        .map(|(index, tool)| py_tool(tool).with_context(|| format!("Invalid tool at index {index}")))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok((messages, tools))
+}
+
+fn py_message(message: &PyAny) -> Result<ChatMessage> {
+    let message = match json_or_dict(message)? {
+        Some(message) => message,
+        None => {
+            let (kind, fields) = langchain_fields(message)?.with_context(|| {
+                format!(
+                    "Unsupported message type `{}`, expected a dict, a JSON string or a LangChain message",
+                    type_name(message)
+                )
+            })?;
+            from_langchain(&kind, fields)?
+        }
+    };
+
+    if !message.is_object() {
+        bail!("Expected a JSON object, got `{message}`");
+    }
+    Ok(serde_json::from_value(message)?)
+}
+
+fn py_tool(tool: &PyAny) -> Result<ToolDefinition> {
+    let tool = json_or_dict(tool)?.with_context(|| {
+        format!("Unsupported tool type `{}`, expected a dict or a JSON string", type_name(tool))
+    })?;
+
+    if !tool.is_object() {
+        bail!("Expected a JSON object, got `{tool}`");
+    }
+    Ok(serde_json::from_value(tool)?)
+}
+
+/// Parses a JSON string or converts a dict, `None` for any other value
+fn json_or_dict(value: &PyAny) -> Result<Option<Value>> {
+    if let Ok(json) = value.downcast::<PyString>() {
+        let json = serde_json::from_str(json.to_str()?).context("Error parsing the JSON string")?;
+        return Ok(Some(json));
+    }
+    if value.is_instance_of::<PyDict>() {
+        return Ok(Some(py_to_json(value, "")?));
+    }
+    Ok(None)
+}
+
+/// Fields of a LangChain message read by [`from_langchain`]
+const LANGCHAIN_FIELDS: [&str; 5] = ["content", "role", "name", "tool_call_id", "tool_calls"];
+
+/// Message type (e.g. `human` or `HumanMessage`) and fields of a LangChain message
+fn langchain_fields(message: &PyAny) -> Result<Option<(String, Map<String, Value>)>> {
+    for method in ["model_dump", "dict"] {
+        if !message.hasattr(method)? {
+            continue;
+        }
+        let Ok(fields) = message.call_method0(method)?.downcast::<PyDict>() else {
+            continue;
+        };
+        let kind = fields
+            .get_item("type")?
+            .and_then(|kind| kind.extract::<String>().ok())
+            .context("Missing or non-string field `type`")?;
+        return Ok(Some((kind, selected_fields(fields)?)));
+    }
+
+    // {"lc": 1, "type": "constructor", "id": ["langchain", "schema", "messages", "HumanMessage"], "kwargs": {...}}
+    if message.hasattr("to_json")? {
+        let serialized = message.call_method0("to_json")?;
+        let class = serialized
+            .get_item("id")
+            .ok()
+            .and_then(|id| id.extract::<Vec<String>>().ok())
+            .and_then(|id| id.last().cloned());
+        let kwargs = serialized.get_item("kwargs").ok();
+        if let (Some(class), Some(kwargs)) = (class, kwargs.and_then(|kwargs| kwargs.downcast::<PyDict>().ok())) {
+            return Ok(Some((class, selected_fields(kwargs)?)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Converts the fields used in a chat message, the others may hold values without a JSON form
+fn selected_fields(fields: &PyDict) -> Result<Map<String, Value>> {
+    let mut selected = Map::new();
+    for field in LANGCHAIN_FIELDS {
+        if let Some(value) = fields.get_item(field)? {
+            selected.insert(field.to_string(), py_to_json(value, field)?);
+        }
+    }
+    // Only the tool calls, the other additional kwargs are provider specific
+    let additional_tool_calls = fields
+        .get_item("additional_kwargs")?
+        .and_then(|kwargs| kwargs.get_item("tool_calls").ok());
+    if let Some(tool_calls) = additional_tool_calls {
+        selected.insert(
+            "additional_tool_calls".to_string(),
+            py_to_json(tool_calls, "additional_kwargs.tool_calls")?,
+        );
+    }
+    Ok(selected)
+}
+
+/// Builds the OpenAI chat message of a LangChain message
+fn from_langchain(kind: &str, fields: Map<String, Value>) -> Result<Value> {
+    let role = match kind.trim_end_matches("Chunk") {
+        "human" | "HumanMessage" => "user",
+        "ai" | "AIMessage" => "assistant",
+        "system" | "SystemMessage" => "system",
+        "tool" | "ToolMessage" => "tool",
+        "chat" | "ChatMessage" => fields
+            .get("role")
+            .and_then(Value::as_str)
+            .context("Missing or non-string field `role`")?,
+        _ => bail!("Unsupported LangChain message type `{kind}`"),
+    };
+    let content = fields.get("content").context("Missing field `content`")?;
+
+    let mut message = json!({ "role": role, "content": content });
+    if let Some(name) = fields.get("name").filter(|name| name.is_string()) {
+        message["name"] = name.clone();
+    }
+
+    match role {
+        "tool" => {
+            let tool_call_id = fields
+                .get("tool_call_id")
+                .filter(|id| id.is_string())
+                .context("Missing or non-string field `tool_call_id`")?;
+            message["tool_call_id"] = tool_call_id.clone();
+        }
+        "assistant" => {
+            let tool_calls = match fields.get("tool_calls").and_then(Value::as_array) {
+                Some(tool_calls) if !tool_calls.is_empty() => tool_calls
+                    .iter()
+                    .enumerate()
+                    .map(|(index, tool_call)| {
+                        openai_tool_call(tool_call).with_context(|| format!("Invalid field `tool_calls[{index}]`"))
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+                // Messages built before LangChain parsed tool calls keep them in the OpenAI shape
+                _ => fields
+                    .get("additional_tool_calls")
+                    .and_then(Value::as_array)
+                    .cloned()
+                    .unwrap_or_default(),
+            };
+            if !tool_calls.is_empty() {
+                message["tool_calls"] = Value::Array(tool_calls);
+            }
+            if content == "" && message.get("tool_calls").is_some() {
+                message["content"] = Value::Null;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(message)
+}
+
+/// `{"name", "args", "id"}` of LangChain into `{"id", "type", "function": {"name", "arguments"}}`
+fn openai_tool_call(tool_call: &Value) -> Result<Value> {
+    let name = tool_call["name"].as_str().context("Missing or non-string field `name`")?;
+    let arguments = match &tool_call["args"] {
+        Value::Null => "{}".to_string(),
+        args => args.to_string(),
+    };
+
+    Ok(json!({
+        "id": tool_call["id"],
+        "type": "function",
+        "function": { "name": name, "arguments": arguments },
+    }))
+}
+
+/// Converts dicts with string keys, lists, tuples, strings, numbers, booleans and `None`
+fn py_to_json(value: &PyAny, path: &str) -> Result<Value> {
+    if value.is_none() {
+        return Ok(Value::Null);
+    }
+    // Before the integers, `bool` being a subclass of `int`
+    if let Ok(boolean) = value.downcast::<PyBool>() {
+        return Ok(Value::Bool(boolean.is_true()));
+    }
+    if value.is_instance_of::<PyLong>() {
+        if let Ok(integer) = value.extract::<i64>() {
+            return Ok(integer.into());
+        }
+        let integer = value
+            .extract::<u64>()
+            .map_err(|_| anyhow!("Field `{path}` is out of the 64-bit integer range"))?;
+        return Ok(integer.into());
+    }
+    if let Ok(float) = value.downcast::<PyFloat>() {
+        return serde_json::Number::from_f64(float.value())
+            .map(Value::Number)
+            .with_context(|| format!("Field `{path}` is not a finite number"));
+    }
+    if let Ok(string) = value.downcast::<PyString>() {
+        return Ok(Value::String(string.to_str()?.to_string()));
+    }
+    if let Ok(dict) = value.downcast::<PyDict>() {
+        let mut object = Map::new();
+        for (key, item) in dict {
+            let Ok(key) = key.downcast::<PyString>() else {
+                bail!("Field `{path}` has a non-string key `{key}`");
+            };
+            let key = key.to_str()?;
+            let item_path = if path.is_empty() { key.to_string() } else { format!("{path}.{key}") };
+            object.insert(key.to_string(), py_to_json(item, &item_path)?);
+        }
+        return Ok(Value::Object(object));
+    }
+    if value.is_instance_of::<PyList>() || value.is_instance_of::<PyTuple>() {
+        return value
+            .iter()?
+            .enumerate()
+            .map(|(index, item)| py_to_json(item?, &format!("{path}[{index}]")))
+            .collect::<Result<Vec<_>>>()
+            .map(Value::Array);
+    }
+
+    bail!("Field `{path}` has the unsupported type `{}`", type_name(value))
+}
+
+fn type_name(value: &PyAny) -> String {
+    value
+        .get_type()
+        .name()
+        .map(str::to_string)
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_langchain_messages() -> Result<()> {
+        let ai = Map::from_iter([
+            ("content".to_string(), json!("")),
+            ("tool_calls".to_string(), json!([{ "name": "search", "args": { "query": "sf" }, "id": "call_1" }])),
+        ]);
+        assert_eq!(
+            from_langchain("AIMessageChunk", ai)?,
+            json!({
+                "role": "assistant",
+                "content": null,
+                "tool_calls": [{
+                    "id": "call_1",
+                    "type": "function",
+                    "function": { "name": "search", "arguments": "{\"query\":\"sf\"}" },
+                }],
+            })
+        );
+
+        let tool = Map::from_iter([("content".to_string(), json!("sunny"))]);
+        let error = from_langchain("tool", tool).unwrap_err();
+        assert!(format!("{error:#}").contains("tool_call_id"));
+
+        let human = Map::from_iter([("content".to_string(), json!([{ "type": "text", "text": "hi" }]))]);
+        assert_eq!(
+            from_langchain("HumanMessage", human)?,
+            json!({ "role": "user", "content": [{ "type": "text", "text": "hi" }] })
+        );
+        Ok(())
+    }
+}
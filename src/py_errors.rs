@@ -0,0 +1,115 @@
+use crate::errors::{ErrorStage, ModelApiError as ApiError, NotarisedApiError, VerificationFailure};
+use pyo3::exceptions::PyException;
+use pyo3::prelude::PyModule;
+use pyo3::types::PyBaseException;
+use pyo3::{create_exception, IntoPy, PyErr, PyResult, Python};
+
+create_exception!(tlsn_langchain, TlsnLangchainError, PyException, "Base class of the errors raised by tlsn_langchain.");
+create_exception!(tlsn_langchain, InvalidInputError, TlsnLangchainError, "Malformed messages, tools or settings.");
+create_exception!(tlsn_langchain, NotaryConnectionError, TlsnLangchainError, "The notary could not be reached or set up.");
+create_exception!(tlsn_langchain, ServerConnectionError, TlsnLangchainError, "The model API could not be reached over a notarised TLS connection.");
+create_exception!(tlsn_langchain, ModelApiError, TlsnLangchainError, "The model API answered with an error status.");
+create_exception!(tlsn_langchain, RateLimitError, ModelApiError, "The model API rate limited the request.");
+create_exception!(tlsn_langchain, AuthenticationError, ModelApiError, "The model API rejected the credentials.");
+create_exception!(tlsn_langchain, BadRequestError, ModelApiError, "The model API rejected the request.");
+create_exception!(tlsn_langchain, ModelServerError, ModelApiError, "The model API failed to serve the request.");
+create_exception!(tlsn_langchain, NotarisationError, TlsnLangchainError, "The session could not be notarised.");
+create_exception!(tlsn_langchain, ProofBuildError, TlsnLangchainError, "The proof could not be built from the notarised session.");
+create_exception!(tlsn_langchain, ProofVerificationError, TlsnLangchainError, "The proof is invalid.");
+
+pub(crate) fn register(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add("TlsnLangchainError", py.get_type::<TlsnLangchainError>())?;
+    m.add("InvalidInputError", py.get_type::<InvalidInputError>())?;
+    m.add("NotaryConnectionError", py.get_type::<NotaryConnectionError>())?;
+    m.add("ServerConnectionError", py.get_type::<ServerConnectionError>())?;
+    m.add("ModelApiError", py.get_type::<ModelApiError>())?;
+    m.add("RateLimitError", py.get_type::<RateLimitError>())?;
+    m.add("AuthenticationError", py.get_type::<AuthenticationError>())?;
+    m.add("BadRequestError", py.get_type::<BadRequestError>())?;
+    m.add("ModelServerError", py.get_type::<ModelServerError>())?;
+    m.add("NotarisationError", py.get_type::<NotarisationError>())?;
+    m.add("ProofBuildError", py.get_type::<ProofBuildError>())?;
+    m.add("ProofVerificationError", py.get_type::<ProofVerificationError>())?;
+    Ok(())
+}
+
+/// Converts an error into the matching Python exception.
+///
+/// Every exception carries a `stage` attribute, and model API errors also carry
+/// `status_code`, `body`, `retry_after` and `proof` (set when the error response was notarised).
+/// Proof verification errors carry a `reason` when the proof itself was rejected.
+pub(crate) fn to_py_err(e: anyhow::Error) -> PyErr {
+    let message = format!("{e:#}");
+
+    let notarised = e.downcast_ref::<NotarisedApiError>();
+    let api_error = notarised
+        .map(|notarised| &notarised.error)
+        .or_else(|| e.downcast_ref::<ApiError>());
+
+    if let Some(api_error) = api_error {
+        let err = match api_error {
+            ApiError::RateLimited { .. } => RateLimitError::new_err(message),
+            ApiError::AuthFailed { .. } => AuthenticationError::new_err(message),
+            ApiError::BadRequest { .. } => BadRequestError::new_err(message),
+            ApiError::ServerError { .. } => ModelServerError::new_err(message),
+            ApiError::Unexpected { .. } => ModelApiError::new_err(message),
+        };
+        let retry_after = match api_error {
+            ApiError::RateLimited { retry_after, .. } => *retry_after,
+            _ => None,
+        };
+
+        return with_attributes(err, ErrorStage::ModelRequest, |value| {
+            value.setattr("status_code", api_error.status())?;
+            value.setattr("body", api_error.body())?;
+            value.setattr("retry_after", retry_after)?;
+            let proof = notarised.map(|notarised| notarised.proof.clone());
+            value.setattr("proof", proof.into_py(value.py()))
+        });
+    }
+
+    let stage = e.downcast_ref::<ErrorStage>().copied();
+    let err = match stage {
+        Some(ErrorStage::InvalidInput) => InvalidInputError::new_err(message),
+        Some(ErrorStage::NotaryConnection) => NotaryConnectionError::new_err(message),
+        Some(ErrorStage::ServerConnection) => ServerConnectionError::new_err(message),
+        // e.g. an unreadable response or a model missing from the list, which carry no status
+        Some(ErrorStage::ModelRequest) => ModelApiError::new_err(message),
+        Some(ErrorStage::Notarisation) => NotarisationError::new_err(message),
+        Some(ErrorStage::ProofBuild) => ProofBuildError::new_err(message),
+        Some(ErrorStage::ProofVerification) => ProofVerificationError::new_err(message),
+        None => TlsnLangchainError::new_err(message),
+    };
+
+    let failure = e.downcast_ref::<VerificationFailure>().copied();
+    match stage {
+        Some(ErrorStage::ModelRequest) => with_attributes(err, ErrorStage::ModelRequest, |value| {
+            for attribute in ["status_code", "body", "retry_after", "proof"] {
+                value.setattr(attribute, value.py().None())?;
+            }
+            Ok(())
+        }),
+        Some(stage) => with_attributes(err, stage, |value| match failure {
+            Some(failure) => value.setattr("reason", failure.as_str()),
+            None => Ok(()),
+        }),
+        None => err,
+    }
+}
+
+fn with_attributes(
+    err: PyErr,
+    stage: ErrorStage,
+    set_attributes: impl FnOnce(&PyBaseException) -> PyResult<()>,
+) -> PyErr {
+    let result = Python::with_gil(|py| {
+        let value = err.value(py);
+        value.setattr("stage", stage.as_str())?;
+        set_attributes(value)
+    });
+
+    match result {
+        Ok(()) => err,
+        Err(setattr_err) => setattr_err,
+    }
+}
@@ -0,0 +1,321 @@
+use crate::body_disclosure::http_heads;
+use crate::tlsn_operations::find_ranges;
+use anyhow::{Context, Result};
+use regex::{Regex, RegexBuilder};
+use std::ops::Range;
+
+/// Header values shorter than this are only redacted where their header is, searching the whole
+/// transcripts for them would redact unrelated bytes. Registered secrets are searched whatever their length.
+const MIN_HEADER_SECRET_LEN: usize = 8;
+
+/// Matches header names, ignoring their case
+#[derive(Debug, Clone)]
+pub enum HeaderPattern {
+    Exact(String),
+    /// `*` matches any run of characters, `?` any single character
+    Glob(String),
+    Regex(Regex),
+}
+
+impl HeaderPattern {
+    /// `re:<regex>` is a regular expression, a pattern with `*` or `?` a glob and anything else a header name
+    pub fn parse(pattern: &str) -> Result<Self> {
+        if let Some(regex) = pattern.strip_prefix("re:") {
+            let regex = RegexBuilder::new(regex)
+                .case_insensitive(true)
+                .build()
+                .with_context(|| format!("Invalid header regex `{regex}`"))?;
+            Ok(Self::Regex(regex))
+        } else if pattern.contains(['*', '?']) {
+            Ok(Self::Glob(pattern.to_ascii_lowercase()))
+        } else {
+            Ok(Self::Exact(pattern.to_ascii_lowercase()))
+        }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            Self::Exact(exact) => name.eq_ignore_ascii_case(exact),
+            Self::Glob(glob) => glob_matches(glob.as_bytes(), name.to_ascii_lowercase().as_bytes()),
+            Self::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+/// Part of a matching header line that is redacted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedactionScope {
+    /// The header value, the name stays revealed
+    Value,
+    /// The whole header line, so even the presence of the header is hidden
+    Line,
+}
+
+#[derive(Debug, Clone)]
+pub struct HeaderRule {
+    pub pattern: HeaderPattern,
+    pub scope: RedactionScope,
+}
+
+impl HeaderRule {
+    pub fn parse(pattern: &str, scope: RedactionScope) -> Result<Self> {
+        Ok(Self {
+            pattern: HeaderPattern::parse(pattern)?,
+            scope,
+        })
+    }
+
+    /// Redacts the value of the header with this name
+    pub fn value_of(name: &str) -> Self {
+        Self {
+            pattern: HeaderPattern::Exact(name.to_ascii_lowercase()),
+            scope: RedactionScope::Value,
+        }
+    }
+}
+
+/// Private ranges of the sent and received transcripts.
+///
+/// The headers matching the rules are redacted in place, and their values are registered as secrets
+/// together with `secrets`. Every secret is then redacted wherever it appears in either transcript,
+/// whatever the case of the header that carried it, and also in its usual encodings (without the
+/// auth scheme, unquoted, JSON escaped), so e.g. an API key echoed in an error body stays private.
+pub(crate) fn private_ranges(
+    sent: &[u8],
+    recv: &[u8],
+    request_rules: &[HeaderRule],
+    response_rules: &[HeaderRule],
+    secrets: &[String],
+) -> Result<(Vec<Range<usize>>, Vec<Range<usize>>)> {
+    let sent_headers = matching_headers(sent, false, request_rules);
+    let recv_headers = matching_headers(recv, true, response_rules);
+
+    let mut all_secrets = vec![];
+    for secret in secrets {
+        add_secret(&mut all_secrets, secret.as_bytes(), 1);
+    }
+    for header in &sent_headers {
+        add_secret(&mut all_secrets, &sent[header.value.clone()], MIN_HEADER_SECRET_LEN);
+    }
+    for header in &recv_headers {
+        add_secret(&mut all_secrets, &recv[header.value.clone()], MIN_HEADER_SECRET_LEN);
+    }
+
+    let redact = |transcript: &[u8], headers: Vec<MatchedHeader>| -> Result<Vec<Range<usize>>> {
+        let (_, mut ranges) = find_ranges(transcript, &all_secrets)?;
+        ranges.extend(headers.into_iter().map(|header| header.redacted));
+        Ok(ranges)
+    };
+
+    Ok((redact(sent, sent_headers)?, redact(recv, recv_headers)?))
+}
+
+#[derive(Debug)]
+struct MatchedHeader {
+    /// Range hidden in the proof, depending on the scope of the rule
+    redacted: Range<usize>,
+    value: Range<usize>,
+}
+
+fn matching_headers(transcript: &[u8], is_response: bool, rules: &[HeaderRule]) -> Vec<MatchedHeader> {
+    let mut matched = vec![];
+
+    for head in http_heads(transcript, is_response) {
+        // The first line is the request or status line
+        for line in header_lines(transcript, head).skip(1) {
+            let Some(colon) = transcript[line.clone()].iter().position(|&b| b == b':') else {
+                continue;
+            };
+            let name = String::from_utf8_lossy(&transcript[line.start..line.start + colon]);
+            let name = name.trim();
+
+            let Some(rule) = rules.iter().find(|rule| rule.pattern.matches(name)) else {
+                continue;
+            };
+
+            let value = trim_range(transcript, line.start + colon + 1..line.end);
+            let redacted = match rule.scope {
+                RedactionScope::Value => value.clone(),
+                RedactionScope::Line => line.clone(),
+            };
+            matched.push(MatchedHeader { redacted, value });
+        }
+    }
+
+    matched
+}
+
+/// Ranges of the lines of a message head, without their line breaks
+fn header_lines(transcript: &[u8], head: Range<usize>) -> impl Iterator<Item = Range<usize>> + '_ {
+    let mut pos = head.start;
+    std::iter::from_fn(move || {
+        if pos >= head.end {
+            return None;
+        }
+        let end = transcript[pos..head.end]
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .map_or(head.end, |i| pos + i);
+        let line = pos..end;
+        pos = end + 2;
+        Some(line)
+    })
+}
+
+fn trim_range(transcript: &[u8], mut range: Range<usize>) -> Range<usize> {
+    while range.start < range.end && transcript[range.start].is_ascii_whitespace() {
+        range.start += 1;
+    }
+    while range.start < range.end && transcript[range.end - 1].is_ascii_whitespace() {
+        range.end -= 1;
+    }
+    range
+}
+
+/// Registers a secret and the forms it may take elsewhere in the transcripts, skipping the forms
+/// shorter than `min_len`
+fn add_secret(secrets: &mut Vec<Vec<u8>>, secret: &[u8], min_len: usize) {
+    let secret = trim_quotes(secret.trim_ascii());
+
+    let mut variants = vec![secret.to_vec()];
+    // `Bearer <token>`, `Basic <credentials>`, ...
+    if let Some(space) = secret.iter().position(|&b| b == b' ') {
+        if secret[..space].iter().all(u8::is_ascii_alphabetic) {
+            variants.push(trim_quotes(secret[space + 1..].trim_ascii()).to_vec());
+        }
+    }
+    for variant in variants.clone() {
+        if let Ok(escaped) = serde_json::to_string(&String::from_utf8_lossy(&variant)) {
+            variants.push(escaped.as_bytes()[1..escaped.len() - 1].to_vec());
+        }
+    }
+
+    for variant in variants {
+        if variant.len() >= min_len && !secrets.contains(&variant) {
+            secrets.push(variant);
+        }
+    }
+}
+
+fn trim_quotes(value: &[u8]) -> &[u8] {
+    match value {
+        [b'"', inner @ .., b'"'] => inner,
+        _ => value,
+    }
+}
+
+fn glob_matches(glob: &[u8], name: &[u8]) -> bool {
+    let (mut g, mut n) = (0, 0);
+    // Position after the last `*` and the name position it was tried at, to backtrack to
+    let mut star = None;
+
+    while n < name.len() {
+        match glob.get(g) {
+            Some(b'*') => {
+                star = Some((g + 1, n));
+                g += 1;
+            }
+            Some(&c) if c == b'?' || c == name[n] => {
+                g += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_g, star_n)) => {
+                    g = star_g;
+                    n = star_n + 1;
+                    star = Some((star_g, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    glob[g..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redacted(transcript: &[u8], ranges: &[Range<usize>]) -> String {
+        let mut transcript = transcript.to_vec();
+        for range in ranges {
+            transcript[range.clone()].fill(b'X');
+        }
+        String::from_utf8(transcript).unwrap()
+    }
+
+    #[test]
+    fn test_header_patterns() -> Result<()> {
+        assert!(HeaderPattern::parse("Authorization")?.matches("authorization"));
+        assert!(HeaderPattern::parse("x-*-key")?.matches("X-Api-Key"));
+        assert!(!HeaderPattern::parse("x-*-key")?.matches("x-api-keys"));
+        assert!(HeaderPattern::parse("x-ratelimit-?")?.matches("x-ratelimit-a"));
+        assert!(HeaderPattern::parse("re:^x-(request|trace)-id$")?.matches("X-Trace-Id"));
+        assert!(!HeaderPattern::parse("re:^x-(request|trace)-id$")?.matches("x-span-id"));
+        assert!(HeaderPattern::parse("re:(").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_headers_are_redacted_whatever_their_case() -> Result<()> {
+        let sent = b"POST /v1/chat/completions HTTP/1.1\r\nAUTHORIZATION:  Bearer sk-secret-token \r\nX-Session: abc\r\ncontent-length: 2\r\n\r\n{}";
+        let rules = [
+            HeaderRule::value_of("authorization"),
+            HeaderRule::parse("x-sess*", RedactionScope::Line)?,
+        ];
+
+        let (sent_private, _) = private_ranges(sent, b"", &rules, &[], &[])?;
+
+        assert_eq!(
+            redacted(sent, &sent_private),
+            "POST /v1/chat/completions HTTP/1.1\r\nAUTHORIZATION:  XXXXXXXXXXXXXXXXXXXXXX \r\nXXXXXXXXXXXXXX\r\ncontent-length: 2\r\n\r\n{}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_secrets_are_redacted_in_both_transcripts() -> Result<()> {
+        let sent = b"POST / HTTP/1.1\r\nauthorization: Bearer sk-secret-token\r\n\r\n";
+        let recv = b"HTTP/1.1 401 Unauthorized\r\ncontent-length: 39\r\n\r\n{\"error\":\"invalid key sk-secret-token\"}";
+        let rules = [HeaderRule::value_of("authorization")];
+
+        let (_, recv_private) = private_ranges(sent, recv, &rules, &[], &[])?;
+
+        assert_eq!(
+            redacted(recv, &recv_private),
+            "HTTP/1.1 401 Unauthorized\r\ncontent-length: 39\r\n\r\n{\"error\":\"invalid key XXXXXXXXXXXXXXX\"}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_registered_secrets_are_redacted_json_escaped() -> Result<()> {
+        let sent = b"POST / HTTP/1.1\r\n\r\n{\"key\":\"pass\\\"word/1\"}";
+
+        let (sent_private, _) = private_ranges(sent, b"", &[], &[], &["pass\"word/1".to_string()])?;
+
+        assert_eq!(redacted(sent, &sent_private), "POST / HTTP/1.1\r\n\r\n{\"key\":\"XXXXXXXXXXXX\"}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_short_secrets_are_redacted_only_when_registered() -> Result<()> {
+        let sent = b"POST / HTTP/1.1\r\nx-pin: 4821\r\n\r\n{\"pin\":\"4821\",\"code\":\"ab12\"}";
+        let rules = [HeaderRule::value_of("x-pin")];
+
+        // A short header value is only redacted in its header
+        let (sent_private, _) = private_ranges(sent, b"", &rules, &[], &[])?;
+        assert_eq!(
+            redacted(sent, &sent_private),
+            "POST / HTTP/1.1\r\nx-pin: XXXX\r\n\r\n{\"pin\":\"4821\",\"code\":\"ab12\"}"
+        );
+
+        let (sent_private, _) = private_ranges(sent, b"", &rules, &[], &["ab12".to_string()])?;
+        assert_eq!(
+            redacted(sent, &sent_private),
+            "POST / HTTP/1.1\r\nx-pin: XXXX\r\n\r\n{\"pin\":\"4821\",\"code\":\"XXXX\"}"
+        );
+        Ok(())
+    }
+}
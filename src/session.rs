@@ -0,0 +1,189 @@
+use crate::chat_params::ChatRequestParams;
+use crate::config::{ApiProvider, Config};
+use crate::conversation::{validate_conversation, ChatMessage, ToolDefinition};
+use crate::errors::ErrorStage;
+use crate::model_interactions::{chat_provider, model_list_round, single_interaction_round, ModelList, ModelResponse};
+use crate::proof_verification::Proof;
+use crate::setup_notary::{notary_public_key, setup_connections};
+use crate::tlsn_operations::{build_proof, notarise_session};
+use anyhow::{Context, Result};
+use hyper::client::conn::http1::SendRequest;
+use tlsn_prover::tls::state::Closed;
+use tlsn_prover::tls::{Prover, ProverError};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+/// A notarised TLS session with the model API that spans several interaction rounds.
+///
+/// The MPC setup and the TLS handshake are done once, when the session is created.
+/// Every round is sent over the same kept-alive connection, and [`NotarisedSession::finalize`]
+/// notarises the whole conversation into a single proof.
+pub struct NotarisedSession {
+    config: Config,
+    keep_alive: bool,
+    prover_task: JoinHandle<Result<Prover<Closed>, ProverError>>,
+    request_sender: SendRequest<String>,
+    rounds: usize,
+    /// Public key of the dummy notary, `None` with a remote notary
+    notary_public_key: Option<p256::PublicKey>,
+}
+
+impl NotarisedSession {
+    /// Sets up the notary and opens a kept-alive connection to the model API
+    pub async fn new(config: Config) -> Result<Self> {
+        Self::connect(config, true).await
+    }
+
+    /// Sets up a session; without `keep_alive` the server closes the connection after the first round
+    pub(crate) async fn connect(config: Config, keep_alive: bool) -> Result<Self> {
+        // Reject a misconfigured endpoint before the expensive MPC setup
+        config
+            .model_settings
+            .api_settings
+            .validate()
+            .context("Invalid model API settings")
+            .context(ErrorStage::InvalidInput)?;
+        config
+            .notary_settings
+            .validate()
+            .context("Invalid notary settings")
+            .context(ErrorStage::InvalidInput)?;
+
+        let notary_public_key = if config.notary_settings.dummy_notary {
+            Some(
+                notary_public_key(&config.notary_settings)
+                    .context("Error loading the notary key")
+                    .context(ErrorStage::InvalidInput)?,
+            )
+        } else {
+            None
+        };
+
+        debug!("The system is being setup...");
+
+        let (_, prover_task, request_sender) = setup_connections(&config)
+            .await
+            .context("Error setting up connections")?;
+
+        Ok(Self {
+            config,
+            keep_alive,
+            prover_task,
+            request_sender,
+            rounds: 0,
+            notary_public_key,
+        })
+    }
+
+    /// Sends one round of the conversation and returns the assistant's reply
+    pub async fn send(
+        &mut self,
+        messages: Vec<ChatMessage>,
+        tools: Vec<ToolDefinition>,
+        params: &ChatRequestParams,
+        delta_sender: Option<&UnboundedSender<String>>,
+    ) -> Result<ModelResponse> {
+        if !self.keep_alive && self.rounds > 0 {
+            anyhow::bail!("The connection was closed after the first round");
+        }
+        validate_request(&messages, &tools, params, self.config.model_settings.api_settings.provider)?;
+
+        let messages = messages
+            .iter()
+            .map(serde_json::to_value)
+            .collect::<Result<Vec<_>, _>>()
+            .context("Error serializing the messages")?;
+        let tools = tools
+            .iter()
+            .map(serde_json::to_value)
+            .collect::<Result<Vec<_>, _>>()
+            .context("Error serializing the tools")?;
+
+        // An error response is still a round of the transcript, which can be notarised
+        single_interaction_round(
+            &mut self.request_sender,
+            &mut self.rounds,
+            &self.config,
+            messages,
+            tools,
+            params,
+            self.keep_alive,
+            delta_sender,
+        )
+            .await
+    }
+
+    /// Lists the models offered by the API, as one round of the session
+    pub async fn list_models(&mut self) -> Result<ModelList> {
+        if !self.keep_alive && self.rounds > 0 {
+            anyhow::bail!("The connection was closed after the first round");
+        }
+
+        model_list_round(&mut self.request_sender, &mut self.rounds, &self.config, self.keep_alive).await
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Number of interaction rounds sent so far, including the failed ones that reached the connection
+    pub fn rounds(&self) -> usize {
+        self.rounds
+    }
+
+    /// Closes the connection and stops the prover without notarising anything
+    pub fn abort(self) {
+        debug!("Aborting the session...");
+
+        drop(self.request_sender);
+        self.prover_task.abort();
+    }
+
+    /// Closes the connection and notarises every round of the session into one proof
+    pub async fn finalize(self) -> Result<Proof> {
+        debug!("Shutting down the connection with the API...");
+
+        // Dropping the sender makes the HTTP client close the TLS connection,
+        // which lets the prover task run to completion.
+        drop(self.request_sender);
+
+        // Notarize the session
+        debug!("Notarizing the session...");
+        // The API key never appears in a proof, whatever header or body it ends up in
+        let mut secrets = self.config.privacy_settings.secrets.clone();
+        secrets.push(self.config.model_settings.api_settings.api_key.clone());
+
+        let notarised_session = notarise_session(
+            self.prover_task,
+            &self.config.privacy_settings,
+            &secrets,
+        )
+            .await
+            .context("Error notarizing the session")
+            .context(ErrorStage::Notarisation)?;
+
+        // Build the proof
+        debug!("Building the proof...");
+        let proof = build_proof(notarised_session)
+            .and_then(Proof::new)
+            .context(ErrorStage::ProofBuild)?;
+
+        match self.notary_public_key {
+            Some(notary_public_key) => proof.with_notary_public_key(notary_public_key),
+            None => Ok(proof),
+        }
+    }
+}
+
+/// Checks the conversation and the parameters of a round for `provider`, before anything is sent
+pub(crate) fn validate_request(messages: &[ChatMessage], tools: &[ToolDefinition], params: &ChatRequestParams, provider: ApiProvider) -> Result<()> {
+    validate_conversation(messages, tools)
+        .context("Invalid conversation")
+        .context(ErrorStage::InvalidInput)?;
+    params
+        .validate()
+        .and_then(|()| chat_provider(provider).validate_params(params))
+        .context("Invalid request parameters")
+        .context(ErrorStage::InvalidInput)
+}
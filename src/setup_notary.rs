@@ -3,13 +3,18 @@ use futures::{AsyncRead, AsyncWrite};
 use hyper::client::conn::http1::SendRequest;
 use hyper_util::rt::TokioIo;
 use notary_client::{Accepted, NotarizationRequest, NotaryClient};
+use p256::ecdsa::SigningKey;
 use p256::pkcs8::DecodePrivateKey;
+use rand::rngs::OsRng;
 use tokio::task;
+use ws_stream_tungstenite::WsStream;
 
-use crate::config::Config;
-use std::str;
+use crate::config::{Config, NotaryKey, NotaryProtocol, NotarySettings};
+use crate::errors::ErrorStage;
+use crate::notary_service::request_session;
+use std::sync::OnceLock;
 use tlsn_core::SessionHeader;
-use tlsn_prover::tls::state::Closed;
+use tlsn_prover::tls::state::{Closed, Setup};
 use tlsn_prover::tls::{Prover, ProverConfig, ProverControl, ProverError};
 use tlsn_verifier::tls::{Verifier, VerifierConfig};
 use tokio::task::JoinHandle;
@@ -24,21 +29,42 @@ pub(super) async fn setup_connections(
     JoinHandle<Result<Prover<Closed>, ProverError>>,
     SendRequest<String>,
 )> {
-    let prover = if config.notary_settings.dummy_notary {
+    let prover = setup_prover(config)
+        .await
+        .context(ErrorStage::NotaryConnection)?;
+
+    debug!("Prover setup complete!");
+    connect_to_server(config, prover)
+        .await
+        .context(ErrorStage::ServerConnection)
+}
+
+/// Sets up the MPC backend of the prover with the notary
+pub(crate) async fn setup_prover(config: &Config) -> Result<Prover<Setup>> {
+    let notary_settings = &config.notary_settings;
+
+    let prover = if notary_settings.dummy_notary {
         let (prover_socket, notary_socket) = tokio::io::duplex(1 << 16);
 
         let connection_id = format!("{}_conversation", config.model_settings.id);
+        let signing_key = load_notary_key(notary_settings)?;
 
         // Start a local simple notary service
         task::spawn(run_dummy_notary(
             notary_socket.compat(),
             connection_id.clone(),
+            signing_key,
+            notary_settings.max_sent_data,
+            notary_settings.max_recv_data,
         ));
 
         // A Prover configuration
         let prover_config = ProverConfig::builder()
             .id(&connection_id)
-            .server_dns(config.model_settings.api_settings.server_domain)
+            .server_dns(config.model_settings.api_settings.server_domain.as_str())
+            .root_cert_store(config.model_settings.api_settings.root_cert_store()?)
+            .max_sent_data(notary_settings.max_sent_data)
+            .max_recv_data(notary_settings.max_recv_data)
             .build()
             .context("Error building prover configuration")?;
 
@@ -49,49 +75,134 @@ pub(super) async fn setup_connections(
             .await
             .context("Error setting up prover")?
     } else {
-        // Build a client to connect to the notary server.
-        let notary_client = NotaryClient::builder()
-            .host(config.notary_settings.host)
-            .port(config.notary_settings.port)
-            .path(config.notary_settings.path)
-            .enable_tls(true)
-            .build()
-            .context("Error building notary client")?;
+        match notary_settings.protocol {
+            NotaryProtocol::NotaryServer => {
+                let (session_id, notary_connection) = request_notary_server_session(notary_settings).await?;
+                setup_remote_prover(config, session_id, notary_connection.compat()).await?
+            }
+            NotaryProtocol::Tcp => {
+                let mut notary_connection = connect_to_notary(notary_settings).await?.compat();
+                let session_id = request_session(
+                    &mut notary_connection,
+                    notary_settings.max_sent_data,
+                    notary_settings.max_recv_data,
+                )
+                    .await?;
+                setup_remote_prover(config, session_id, notary_connection).await?
+            }
+            NotaryProtocol::WebSocket => {
+                let notary_socket = connect_to_notary(notary_settings).await?;
+                let url = format!("ws://{}:{}/{}", notary_settings.host, notary_settings.port, notary_settings.path);
+                let (websocket, _) = async_tungstenite::tokio::client_async(url, notary_socket)
+                    .await
+                    .context("Error opening the notary WebSocket")?;
 
-        // Send requests for configuration and notarization to the notary server.
-        let notarization_request = NotarizationRequest::builder()
-            .build()
-            .context("Error building notarization request")?;
-
-        let Accepted {
-            io: notary_connection,
-            id: session_id,
-            ..
-        } = notary_client
-            .request_notarization(notarization_request)
-            .await
-            .context("Error requesting notarization")?;
+                let mut notary_connection = WsStream::new(websocket);
+                let session_id = request_session(
+                    &mut notary_connection,
+                    notary_settings.max_sent_data,
+                    notary_settings.max_recv_data,
+                )
+                    .await?;
+                setup_remote_prover(config, session_id, notary_connection).await?
+            }
+        }
+    };
 
-        // Configure a new prover with the unique session id returned from notary client.
-        let prover_config = ProverConfig::builder()
-            .id(session_id)
-            .server_dns(config.model_settings.api_settings.server_domain)
-            .build()
-            .context("Error building prover configuration")?;
+    Ok(prover)
+}
 
-        // Create a new prover and set up the MPC backend.
-        Prover::new(prover_config)
-            .setup(notary_connection.compat())
-            .await
-            .context("Error setting up prover")?
-    };
+/// Requests a session from a TLSNotary `notary-server` over its HTTP API
+async fn request_notary_server_session(
+    notary_settings: &NotarySettings,
+) -> Result<(String, impl tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin + 'static)> {
+    // Build a client to connect to the notary server.
+    let mut notary_client_builder = NotaryClient::builder();
+    notary_client_builder
+        .host(notary_settings.host.as_str())
+        .port(notary_settings.port)
+        .path(notary_settings.path.as_str())
+        .enable_tls(notary_settings.tls);
+    if let Some(root_cert_store) = notary_settings.root_cert_store()? {
+        notary_client_builder.root_cert_store(root_cert_store);
+    }
+    if let Some(api_key) = &notary_settings.api_key {
+        notary_client_builder.api_key(api_key.as_str());
+    }
+    let notary_client = notary_client_builder
+        .build()
+        .context("Error building notary client")?;
 
-    debug!("Prover setup complete!");
+    // Send requests for configuration and notarization to the notary server.
+    // The notary enforces the same limits as the prover, so they are part of the request.
+    let notarization_request = NotarizationRequest::builder()
+        .max_sent_data(notary_settings.max_sent_data)
+        .max_recv_data(notary_settings.max_recv_data)
+        .build()
+        .context("Error building notarization request")?;
+
+    let Accepted {
+        io: notary_connection,
+        id: session_id,
+        ..
+    } = notary_client
+        .request_notarization(notarization_request)
+        .await
+        .with_context(|| {
+            format!(
+                "Error requesting notarization from {}:{}",
+                notary_settings.host, notary_settings.port
+            )
+        })?;
+
+    Ok((session_id, notary_connection))
+}
+
+async fn connect_to_notary(notary_settings: &NotarySettings) -> Result<tokio::net::TcpStream> {
+    tokio::net::TcpStream::connect((notary_settings.host.as_str(), notary_settings.port))
+        .await
+        .with_context(|| format!("Error connecting to the notary at {}:{}", notary_settings.host, notary_settings.port))
+}
+
+/// Configures a prover with the session id given by the notary and sets up the MPC backend
+async fn setup_remote_prover<T: AsyncRead + AsyncWrite + Send + Unpin + 'static>(
+    config: &Config,
+    session_id: String,
+    notary_connection: T,
+) -> Result<Prover<Setup>> {
+    let notary_settings = &config.notary_settings;
+
+    let prover_config = ProverConfig::builder()
+        .id(session_id)
+        .server_dns(config.model_settings.api_settings.server_domain.as_str())
+        .root_cert_store(config.model_settings.api_settings.root_cert_store()?)
+        .max_sent_data(notary_settings.max_sent_data)
+        .max_recv_data(notary_settings.max_recv_data)
+        .build()
+        .context("Error building prover configuration")?;
+
+    // Create a new prover and set up the MPC backend.
+    Prover::new(prover_config)
+        .setup(notary_connection)
+        .await
+        .context("Error setting up prover")
+}
+
+/// Opens the notarised TLS connection to the model API and attaches an HTTP client to it
+async fn connect_to_server(
+    config: &Config,
+    prover: Prover<Setup>,
+) -> Result<(
+    ProverControl,
+    JoinHandle<Result<Prover<Closed>, ProverError>>,
+    SendRequest<String>,
+)> {
     // Open a new socket to the application server.
-    let client_socket =
-        tokio::net::TcpStream::connect((config.model_settings.api_settings.server_domain, 443))
-            .await
-            .context("Error connecting to server")?;
+    let api_settings = &config.model_settings.api_settings;
+    let address = api_settings.connect_address();
+    let client_socket = tokio::net::TcpStream::connect(address.as_str())
+        .await
+        .with_context(|| format!("Error connecting to server at {address}"))?;
 
     // Bind the Prover to server connection
     let (tls_connection, prover_fut) = prover
@@ -117,21 +228,60 @@ pub(super) async fn setup_connections(
     Ok((prover_ctrl, prover_task, request_sender))
 }
 
+/// Published test key bundled with the crate
+const INSECURE_TEST_NOTARY_KEY: &str = include_str!("../tlsn/notary.key");
+
+/// Loads the signing key of the dummy notary.
+///
+/// The bundled test key is refused without [`NotarySettings::allow_insecure_test_key`],
+/// including when it is passed by path, PEM or environment variable.
+pub fn load_notary_key(notary_settings: &NotarySettings) -> Result<SigningKey> {
+    static EPHEMERAL_KEY: OnceLock<SigningKey> = OnceLock::new();
+
+    let pem = match &notary_settings.notary_key {
+        NotaryKey::Ephemeral => {
+            return Ok(EPHEMERAL_KEY.get_or_init(|| SigningKey::random(&mut OsRng)).clone());
+        }
+        NotaryKey::Path(path) => std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read Notary key from {}", path.display()))?,
+        NotaryKey::Pem(pem) => pem.clone(),
+        NotaryKey::Env(var) => std::env::var(var)
+            .with_context(|| format!("Failed to read Notary key from ${var}"))?,
+        NotaryKey::InsecureTestKey => INSECURE_TEST_NOTARY_KEY.to_string(),
+    };
+
+    let signing_key = SigningKey::from_pkcs8_pem(&pem).context("Failed to parse Notary key")?;
+
+    let test_key = SigningKey::from_pkcs8_pem(INSECURE_TEST_NOTARY_KEY)
+        .context("Failed to parse the bundled Notary key")?;
+    if signing_key == test_key && !notary_settings.allow_insecure_test_key {
+        anyhow::bail!(
+            "Refusing to sign with the published test Notary key, it needs an explicit insecure opt-in"
+        );
+    }
+
+    Ok(signing_key)
+}
+
+/// Public key of the dummy notary, which proofs are verified against
+pub(crate) fn notary_public_key(notary_settings: &NotarySettings) -> Result<p256::PublicKey> {
+    Ok(load_notary_key(notary_settings)?.verifying_key().into())
+}
+
 /// Runs a simple Notary with the provided connection to the Prover.
 pub async fn run_dummy_notary<T: AsyncWrite + AsyncRead + Send + Unpin + 'static>(
     conn: T,
     connection_id: String,
+    signing_key: SigningKey,
+    max_sent_data: usize,
+    max_recv_data: usize,
 ) -> Result<SessionHeader> {
-    // Load the notary signing key
-    let signing_key_str = str::from_utf8(include_bytes!("../tlsn/notary.key"))
-        .context("Failed to read Notary key")?;
-    let signing_key = p256::ecdsa::SigningKey::from_pkcs8_pem(signing_key_str)
-        .context("Failed to parse Notary key")?;
-
     // Setup default config. Normally a different ID would be generated
     // for each notarization.
     let config = VerifierConfig::builder()
         .id(connection_id)
+        .max_sent_data(max_sent_data)
+        .max_recv_data(max_recv_data)
         .build()
         .context("Failed to build verifier config")?;
 
@@ -140,3 +290,133 @@ pub async fn run_dummy_notary<T: AsyncWrite + AsyncRead + Send + Unpin + 'static
         .await
         .context("Error running dummy notary")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ModelApiSettings, ModelSettings, PrivacySettings};
+    use crate::notary_service::{serve_session, ServiceLimits};
+    use notary_server::{
+        run_server, AuthorizationProperties, LoggingProperties, NotarizationProperties,
+        NotaryServerProperties, NotarySigningKeyProperties, ServerProperties, TLSProperties,
+    };
+    use std::time::Duration;
+
+    /// Spawns a notary server without TLS on a free local port and waits for it to listen
+    async fn spawn_notary_server(max_sent_data: usize, max_recv_data: usize) -> Result<u16> {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+
+        let properties = NotaryServerProperties {
+            server: ServerProperties {
+                name: "tlsn-langchain-test-notary".to_string(),
+                host: "127.0.0.1".to_string(),
+                port,
+                html_info: String::new(),
+            },
+            notarization: NotarizationProperties {
+                max_sent_data,
+                max_recv_data,
+            },
+            tls: TLSProperties {
+                enabled: false,
+                private_key_pem_path: String::new(),
+                certificate_pem_path: String::new(),
+            },
+            notary_key: NotarySigningKeyProperties {
+                private_key_pem_path: "tlsn/notary.key".to_string(),
+                public_key_pem_path: "tlsn/notary.pub".to_string(),
+            },
+            logging: LoggingProperties {
+                level: "INFO".to_string(),
+                filter: None,
+            },
+            authorization: AuthorizationProperties {
+                enabled: false,
+                whitelist_csv_path: String::new(),
+            },
+        };
+        tokio::spawn(async move { run_server(&properties).await });
+
+        for _ in 0..50 {
+            if tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+                return Ok(port);
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        anyhow::bail!("The notary server did not start")
+    }
+
+    fn remote_notary_config(port: u16, max_recv_data: usize) -> Config {
+        Config {
+            model_settings: ModelSettings {
+                id: "gpt-4o".to_string(),
+                api_settings: ModelApiSettings::new(String::new()),
+                setup_prompt: None,
+                validate_model: false,
+            },
+            privacy_settings: PrivacySettings::default(),
+            notary_settings: NotarySettings {
+                tls: false,
+                max_recv_data,
+                ..NotarySettings::remote("127.0.0.1".to_string(), port)
+            },
+        }
+    }
+
+    #[test]
+    fn test_notary_key_loading() -> Result<()> {
+        let ephemeral = load_notary_key(&NotarySettings::default())?;
+        assert!(ephemeral == load_notary_key(&NotarySettings::default())?);
+
+        let mut settings = NotarySettings {
+            notary_key: NotaryKey::Path("tlsn/notary.key".into()),
+            ..NotarySettings::default()
+        };
+        assert!(load_notary_key(&settings).is_err());
+
+        settings.allow_insecure_test_key = true;
+        assert!(load_notary_key(&settings)? != ephemeral);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remote_notary_setup() -> Result<()> {
+        let port = spawn_notary_server(1 << 12, 1 << 14).await?;
+
+        let config = remote_notary_config(port, 1 << 14);
+        config.notary_settings.validate()?;
+        setup_prover(&config).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tcp_notary_service_setup() -> Result<()> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await?;
+            let limits = ServiceLimits {
+                max_sent_data: 1 << 12,
+                max_recv_data: 1 << 14,
+                ..ServiceLimits::default()
+            };
+            serve_session(socket.compat(), &SigningKey::random(&mut OsRng), &limits).await
+        });
+
+        let mut config = remote_notary_config(port, 1 << 14);
+        config.notary_settings.protocol = NotaryProtocol::Tcp;
+        config.notary_settings.validate()?;
+        setup_prover(&config).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remote_notary_rejects_oversized_session() -> Result<()> {
+        let port = spawn_notary_server(1 << 12, 1 << 14).await?;
+
+        let config = remote_notary_config(port, 1 << 20);
+        assert!(setup_prover(&config).await.is_err());
+        Ok(())
+    }
+}
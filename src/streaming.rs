@@ -0,0 +1,232 @@
+use crate::model_interactions::CompletionMetadata;
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Map, Value};
+
+/// Incremental parser for `text/event-stream` bodies.
+///
+/// Body frames can split an event anywhere (including in the middle of a
+/// multi-byte character), so bytes are buffered until a blank line closes the event.
+#[derive(Debug, Default)]
+pub(crate) struct SseParser {
+    buffer: Vec<u8>,
+}
+
+impl SseParser {
+    /// Feeds a body chunk and returns the `data` payloads of every event it completed
+    pub(crate) fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut payloads = vec![];
+        while let Some((event_end, separator_len)) = find_event_end(&self.buffer) {
+            let event = self.buffer.drain(..event_end + separator_len).collect::<Vec<u8>>();
+            if let Some(payload) = parse_event(&event[..event_end]) {
+                payloads.push(payload);
+            }
+        }
+
+        payloads
+    }
+
+    /// Flushes an event left unterminated when the body ended
+    pub(crate) fn finish(&mut self) -> Option<String> {
+        let event = std::mem::take(&mut self.buffer);
+        parse_event(&event)
+    }
+}
+
+fn find_event_end(buffer: &[u8]) -> Option<(usize, usize)> {
+    let crlf = buffer.windows(4).position(|w| w == b"\r\n\r\n").map(|idx| (idx, 4));
+    let lf = buffer.windows(2).position(|w| w == b"\n\n").map(|idx| (idx, 2));
+
+    match (crlf, lf) {
+        (Some(crlf), Some(lf)) => Some(if crlf.0 <= lf.0 { crlf } else { lf }),
+        (crlf, lf) => crlf.or(lf),
+    }
+}
+
+fn parse_event(event: &[u8]) -> Option<String> {
+    let event = String::from_utf8_lossy(event);
+
+    let data = event
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(|data| data.strip_prefix(' ').unwrap_or(data))
+        .collect::<Vec<&str>>();
+
+    if data.is_empty() {
+        None
+    } else {
+        Some(data.join("\n"))
+    }
+}
+
+/// Indices a streamed tool call may skip past the last one, for the gaps left by the providers
+/// indexing tool calls by content block. Larger jumps would let the server grow the list at will.
+const MAX_TOOL_CALL_INDEX_GAP: usize = 16;
+
+/// Reassembles the streamed `choices[0].delta` chunks into a complete assistant message
+#[derive(Debug, Default)]
+pub(crate) struct MessageAccumulator {
+    role: Option<String>,
+    content: Option<String>,
+    tool_calls: Vec<Map<String, Value>>,
+    usage: Option<Map<String, Value>>,
+    metadata: CompletionMetadata,
+}
+
+impl MessageAccumulator {
+    pub(crate) fn push(&mut self, delta: &Value) -> Result<()> {
+        if let Some(role) = delta.get("role").and_then(Value::as_str) {
+            self.role = Some(role.to_string());
+        }
+
+        if let Some(content) = delta.get("content").and_then(Value::as_str) {
+            self.content.get_or_insert_with(String::new).push_str(content);
+        }
+
+        let Some(tool_calls) = delta.get("tool_calls").and_then(Value::as_array) else {
+            return Ok(());
+        };
+
+        for tool_call in tool_calls {
+            let index = tool_call
+                .get("index")
+                .and_then(Value::as_u64)
+                .context("Streamed tool call is missing its index")?;
+            if index > (self.tool_calls.len() + MAX_TOOL_CALL_INDEX_GAP) as u64 {
+                bail!(
+                    "Streamed tool call index {index} is out of range, {} tool calls were streamed so far",
+                    self.tool_calls.len()
+                );
+            }
+            let index = index as usize;
+
+            if self.tool_calls.len() <= index {
+                self.tool_calls.resize_with(index + 1, Map::new);
+            }
+            let entry = &mut self.tool_calls[index];
+
+            for key in ["id", "type"] {
+                if let Some(value) = tool_call.get(key).and_then(Value::as_str) {
+                    entry.insert(key.to_string(), json!(value));
+                }
+            }
+
+            if let Some(function) = tool_call.get("function") {
+                let entry_function = entry
+                    .entry("function")
+                    .or_insert_with(|| json!({ "name": "", "arguments": "" }));
+
+                for key in ["name", "arguments"] {
+                    if let Some(fragment) = function.get(key).and_then(Value::as_str) {
+                        let accumulated = entry_function[key].as_str().unwrap_or_default();
+                        entry_function[key] = json!(format!("{accumulated}{fragment}"));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Merges the usage reported by a chunk, later counts replacing earlier ones
+    pub(crate) fn push_usage(&mut self, usage: &Value) {
+        if let Some(usage) = usage.as_object() {
+            let merged = self.usage.get_or_insert_with(Map::new);
+            merged.extend(usage.iter().map(|(key, value)| (key.clone(), value.clone())));
+        }
+    }
+
+    pub(crate) fn push_metadata(&mut self, metadata: CompletionMetadata) {
+        self.metadata.merge(metadata);
+    }
+
+    /// Returns the completion metadata reported over the stream
+    pub(crate) fn metadata(&self) -> &CompletionMetadata {
+        &self.metadata
+    }
+
+    /// Returns the usage reported over the stream
+    pub(crate) fn usage(&self) -> Option<Value> {
+        self.usage.clone().map(Value::Object)
+    }
+
+    /// Returns the message in the same shape as a non-streamed `choices[0].message`
+    pub(crate) fn into_message(self) -> Value {
+        let mut message = Map::new();
+        message.insert(
+            "role".to_string(),
+            json!(self.role.unwrap_or_else(|| "assistant".to_string())),
+        );
+        message.insert("content".to_string(), json!(self.content));
+
+        // Providers indexing tool calls by content block leave gaps for the other blocks
+        let tool_calls = self
+            .tool_calls
+            .into_iter()
+            .filter(|tool_call| !tool_call.is_empty())
+            .collect::<Vec<_>>();
+        if !tool_calls.is_empty() {
+            message.insert("tool_calls".to_string(), json!(tool_calls));
+        }
+
+        Value::Object(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sse_parser_handles_split_events() {
+        let mut parser = SseParser::default();
+
+        assert!(parser.push(b"data: {\"a\":").is_empty());
+        assert_eq!(parser.push(b"1}\n\ndata: [DO"), vec!["{\"a\":1}".to_string()]);
+        assert_eq!(parser.push(b"NE]\r\n\r\n"), vec!["[DONE]".to_string()]);
+        assert_eq!(parser.push(b": keep-alive comment\n\n"), Vec::<String>::new());
+        assert_eq!(parser.finish(), None);
+    }
+
+    #[test]
+    fn test_accumulator_reassembles_tool_calls() -> Result<()> {
+        let mut accumulator = MessageAccumulator::default();
+
+        accumulator.push(&json!({ "role": "assistant", "content": null }))?;
+        accumulator.push(&json!({ "tool_calls": [{
+            "index": 0, "id": "call_1", "type": "function",
+            "function": { "name": "tavily_search_results_json", "arguments": "" }
+        }] }))?;
+        accumulator.push(&json!({ "tool_calls": [{ "index": 0, "function": { "arguments": "{\"query\":" } }] }))?;
+        accumulator.push(&json!({ "tool_calls": [{ "index": 0, "function": { "arguments": "\"sf\"}" } }] }))?;
+
+        let message = accumulator.into_message();
+        assert_eq!(message["role"], "assistant");
+        assert_eq!(message["content"], Value::Null);
+        assert_eq!(message["tool_calls"][0]["id"], "call_1");
+        assert_eq!(
+            message["tool_calls"][0]["function"]["arguments"],
+            "{\"query\":\"sf\"}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_accumulator_rejects_out_of_range_tool_call_index() -> Result<()> {
+        let mut accumulator = MessageAccumulator::default();
+
+        // Gaps left by other content blocks are accepted
+        accumulator.push(&json!({ "tool_calls": [{ "index": 2, "id": "toolu_1", "function": { "name": "search" } }] }))?;
+        assert!(accumulator
+            .push(&json!({ "tool_calls": [{ "index": u64::MAX, "function": { "arguments": "{}" } }] }))
+            .is_err());
+        assert!(accumulator
+            .push(&json!({ "tool_calls": [{ "index": 1u64 << 40, "function": { "arguments": "{}" } }] }))
+            .is_err());
+
+        let message = accumulator.into_message();
+        assert_eq!(message["tool_calls"].as_array().map(Vec::len), Some(1));
+        Ok(())
+    }
+}
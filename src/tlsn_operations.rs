@@ -1,5 +1,8 @@
+use crate::body_disclosure::{private_body_ranges, BodyDisclosure};
+use crate::config::PrivacySettings;
+use crate::redaction;
+use aho_corasick::AhoCorasick;
 use anyhow::Context;
-use hyper::HeaderMap;
 use std::ops::Range;
 use tlsn_core::commitment::CommitmentId;
 use tlsn_core::proof::TlsProof;
@@ -11,8 +14,8 @@ use tracing::debug;
 
 pub(super) async fn notarise_session(
     prover_task: JoinHandle<anyhow::Result<Prover<Closed>, ProverError>>,
-    recv_private_data: &[Vec<u8>],
-    sent_private_data: &[Vec<u8>],
+    privacy_settings: &PrivacySettings,
+    secrets: &[String],
 ) -> anyhow::Result<(Vec<CommitmentId>, Vec<CommitmentId>, NotarizedSession)> {
     // The Prover task should be done now, so we can grab it.
     let prover = prover_task
@@ -23,33 +26,41 @@ pub(super) async fn notarise_session(
     let mut prover = prover.start_notarize();
 
     // Notarize the session
-    let (public_sent_commitment_ids, _) = find_ranges(
+    let (sent_private_ranges, recv_private_ranges) = redaction::private_ranges(
         prover.sent_transcript().data(),
-        &sent_private_data
-            .iter()
-            .map(|v| v.as_slice())
-            .collect::<Vec<&[u8]>>(),
+        prover.recv_transcript().data(),
+        &privacy_settings.request_header_rules,
+        &privacy_settings.response_header_rules,
+        secrets,
+    )?;
+
+    let public_sent_commitment_ids = public_ranges(
+        prover.sent_transcript().data(),
+        sent_private_ranges,
+        &privacy_settings.request_body_disclosure,
+        false,
     );
 
-    let (public_received_commitment_ids, _) = find_ranges(
+    let public_received_commitment_ids = public_ranges(
         prover.recv_transcript().data(),
-        &recv_private_data
-            .iter()
-            .map(|v| v.as_slice())
-            .collect::<Vec<&[u8]>>(),
+        recv_private_ranges,
+        &privacy_settings.response_body_disclosure,
+        true,
     );
 
     let builder = prover.commitment_builder();
 
     let sent_commitment_ids = public_sent_commitment_ids
         .iter()
-        .map(|range| builder.commit_sent(range).unwrap())
-        .collect::<Vec<_>>();
+        .map(|range| builder.commit_sent(range))
+        .collect::<Result<Vec<_>, _>>()
+        .context("Error committing to the sent data")?;
 
     let recived_commitment_ids = public_received_commitment_ids
         .iter()
-        .map(|range| builder.commit_recv(range).unwrap())
-        .collect::<Vec<_>>();
+        .map(|range| builder.commit_recv(range))
+        .collect::<Result<Vec<_>, _>>()
+        .context("Error committing to the received data")?;
 
     // Finalize, returning the notarized session
     let notarized_session = prover
@@ -72,66 +83,188 @@ pub(super) fn build_proof(
         Vec<CommitmentId>,
         NotarizedSession,
     ),
-) -> TlsProof {
+) -> anyhow::Result<TlsProof> {
     let session_proof = notarized_session.session_proof();
 
     let mut proof_builder = notarized_session.data().build_substrings_proof();
 
     for id in sent_commitment_ids {
-        proof_builder.reveal_by_id(id).unwrap();
+        proof_builder
+            .reveal_by_id(id)
+            .context("Error revealing the sent data")?;
     }
     for id in received_commitment_ids {
-        proof_builder.reveal_by_id(id).unwrap();
+        proof_builder
+            .reveal_by_id(id)
+            .context("Error revealing the received data")?;
     }
 
-    let substrings_proof = proof_builder.build().unwrap();
+    let substrings_proof = proof_builder
+        .build()
+        .context("Error building the substrings proof")?;
 
-    TlsProof {
+    Ok(TlsProof {
         session: session_proof,
         substrings: substrings_proof,
-    }
+    })
 }
 
-pub(super) fn extract_private_data(
-    recv_private_data: &mut Vec<Vec<u8>>,
-    headers: &HeaderMap,
-    topics_to_censor: &[&str],
-) {
-    for (header_name, header_value) in headers {
-        if topics_to_censor.contains(&header_name.as_str()) {
-            let header_value = header_value.as_bytes().to_vec();
-            if !recv_private_data.contains(&header_value) {
-                recv_private_data.push(header_value);
-            }
-        }
-    }
+/// Ranges of a transcript revealed in the proof: everything but the redacted ranges
+/// and the parts of the bodies the disclosure rules keep private.
+fn public_ranges(
+    transcript: &[u8],
+    mut private_ranges: Vec<Range<usize>>,
+    body_disclosure: &BodyDisclosure,
+    is_response: bool,
+) -> Vec<Range<usize>> {
+    private_ranges.extend(private_body_ranges(transcript, body_disclosure, is_response));
+
+    complement_ranges(transcript.len(), private_ranges)
 }
 
-fn find_ranges(seq: &[u8], sub_seq: &[&[u8]]) -> (Vec<Range<usize>>, Vec<Range<usize>>) {
-    let mut private_ranges = Vec::new();
-    for s in sub_seq {
-        for (idx, w) in seq.windows(s.len()).enumerate() {
-            if w == *s {
-                private_ranges.push(idx..(idx + w.len()));
-            }
+/// Splits `seq` into the ranges free of any of the `sub_seq` and the ranges covered by them.
+///
+/// All the patterns are searched in a single pass (Aho-Corasick), overlapping occurrences included.
+/// Both lists are sorted, disjoint and together partition `0..seq.len()`.
+pub fn find_ranges<P: AsRef<[u8]>>(
+    seq: &[u8],
+    sub_seq: &[P],
+) -> anyhow::Result<(Vec<Range<usize>>, Vec<Range<usize>>)> {
+    let patterns = sub_seq
+        .iter()
+        .map(AsRef::as_ref)
+        .filter(|pattern| !pattern.is_empty())
+        .collect::<Vec<&[u8]>>();
+
+    let private_ranges = if patterns.is_empty() {
+        vec![]
+    } else {
+        let matcher = AhoCorasick::new(&patterns).context("Error building the private data matcher")?;
+        merge_ranges(
+            matcher
+                .find_overlapping_iter(seq)
+                .map(|found| found.range())
+                .collect(),
+        )
+    };
+
+    let public_ranges = complement_ranges(seq.len(), private_ranges.clone());
+
+    Ok((public_ranges, private_ranges))
+}
+
+/// Sorts the ranges and merges the overlapping or adjacent ones
+fn merge_ranges(mut ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    ranges.sort_by_key(|r| r.start);
+
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for r in ranges.into_iter().filter(|r| !r.is_empty()) {
+        match merged.last_mut() {
+            Some(last) if r.start <= last.end => last.end = last.end.max(r.end),
+            _ => merged.push(r),
         }
     }
 
-    let mut sorted_ranges = private_ranges.clone();
-    sorted_ranges.sort_by_key(|r| r.start);
+    merged
+}
 
-    let mut public_ranges = Vec::new();
+/// Returns the ranges of `0..len` not covered by any of the (possibly overlapping) `ranges`
+fn complement_ranges(len: usize, ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    let mut complement = Vec::new();
     let mut last_end = 0;
-    for r in sorted_ranges {
+    for r in merge_ranges(ranges) {
         if r.start > last_end {
-            public_ranges.push(last_end..r.start);
+            complement.push(last_end..r.start.min(len));
         }
         last_end = r.end;
     }
 
-    if last_end < seq.len() {
-        public_ranges.push(last_end..seq.len());
+    if last_end < len {
+        complement.push(last_end..len);
     }
 
-    (public_ranges, private_ranges)
+    complement
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Occurrences of the patterns, found the slow way
+    fn naive_occurrences(seq: &[u8], patterns: &[Vec<u8>]) -> Vec<Range<usize>> {
+        let mut occurrences = vec![];
+        for pattern in patterns.iter().filter(|p| !p.is_empty()) {
+            for (idx, w) in seq.windows(pattern.len()).enumerate() {
+                if w == pattern.as_slice() {
+                    occurrences.push(idx..idx + pattern.len());
+                }
+            }
+        }
+        occurrences
+    }
+
+    #[test]
+    fn test_nested_secrets_stay_private() -> anyhow::Result<()> {
+        let seq = b"Bearer sk-secret-key and sk-secret";
+        let (public, private) = find_ranges(seq, &[b"sk-secret-key".as_slice(), b"secret".as_slice()])?;
+
+        assert_eq!(private, vec![7..20, 28..34]);
+        assert_eq!(public, vec![0..7, 20..28]);
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn prop_ranges_partition_transcript(
+            seq in proptest::collection::vec(0u8..4, 0..512),
+            patterns in proptest::collection::vec(proptest::collection::vec(0u8..4, 0..6), 0..5),
+        ) {
+            let (public, private) = find_ranges(&seq, &patterns).unwrap();
+
+            // Sorted, disjoint, non-empty and covering the whole transcript
+            let mut all = public.iter().chain(private.iter()).cloned().collect::<Vec<_>>();
+            all.sort_by_key(|r| r.start);
+            let mut pos = 0;
+            for range in &all {
+                prop_assert!(!range.is_empty());
+                prop_assert_eq!(range.start, pos);
+                pos = range.end;
+            }
+            prop_assert_eq!(pos, seq.len());
+
+            // The private ranges are exactly the bytes covered by an occurrence
+            let mut covered = vec![false; seq.len()];
+            for occurrence in naive_occurrences(&seq, &patterns) {
+                covered[occurrence].iter_mut().for_each(|byte| *byte = true);
+            }
+            for range in &private {
+                prop_assert!(covered[range.clone()].iter().all(|&byte| byte));
+            }
+            for range in &public {
+                prop_assert!(covered[range.clone()].iter().all(|&byte| !byte));
+            }
+        }
+
+        #[test]
+        fn prop_complement_is_disjoint(
+            len in 0usize..256,
+            ranges in proptest::collection::vec((0usize..256, 0usize..32), 0..16),
+        ) {
+            let ranges = ranges
+                .into_iter()
+                .map(|(start, len_)| start.min(len)..(start + len_).min(len))
+                .collect::<Vec<_>>();
+            let complement = complement_ranges(len, ranges.clone());
+
+            for gap in &complement {
+                prop_assert!(!gap.is_empty());
+                for range in &ranges {
+                    prop_assert!(gap.end <= range.start || range.end <= gap.start || range.is_empty());
+                }
+            }
+            let covered = merge_ranges(ranges).iter().map(|r| r.len()).sum::<usize>();
+            prop_assert_eq!(covered + complement.iter().map(|r| r.len()).sum::<usize>(), len);
+        }
+    }
 }